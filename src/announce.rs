@@ -1,21 +1,33 @@
 use std::sync::{Arc, RwLock};
 
-use log::{error, info, warn};
+use tracing::{error, info, warn, Span};
 
 use serde::{Deserialize, Serialize};
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
+
+use clap::Args;
 
 use crate::cross_seed::{cross_seed_announce, AnnounceRequest};
+use crate::db::{EntryFields, Origin, Record, Status};
+use crate::history::{db, finished_response, record_request};
+use crate::jobs::run_job;
+use crate::notify::{notify, Event, EventKind, Release};
 use crate::AppState;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Args)]
 pub(crate) struct Announce {
+    /// Name of the release
+    #[arg(long)]
     name: String,
+    #[arg(long)]
     guid: String,
+    /// Download link of the torrent file
+    #[arg(long)]
     link: String,
+    #[arg(long)]
     tracker: String,
 }
 
@@ -30,10 +42,15 @@ impl From<Announce> for AnnounceRequest {
     }
 }
 
-async fn do_announce(state: Arc<RwLock<AppState>>, announce: Announce) -> anyhow::Result<bool> {
+async fn do_announce(
+    state: Arc<RwLock<AppState>>,
+    announce: Announce,
+    record: &Record,
+) -> anyhow::Result<bool> {
     let name = announce.name.clone();
+    Span::current().record("release_title", &name);
 
-    info!("[/announce] Release {name} checking...");
+    info!("Release {name} checking...");
 
     let (
         cross_seed_seedbox_url,
@@ -64,19 +81,30 @@ async fn do_announce(state: Arc<RwLock<AppState>>, announce: Announce) -> anyhow
         let cross_seed_api_key =
             cross_seed_seedbox_api_key.context("No API key for cross-seed seedbox found.")?;
 
-        if let Ok(status_code) = cross_seed_announce(
-            cross_seed_url,
-            cross_seed_api_key,
-            &(announce.clone().into()),
-        )
-        .await
-        {
-            if status_code.as_u16() == 200 {
-                info!("[/announce] Release {name} accepted by cross-seed-seedbox.");
-                any_success = true;
-            }
+        let result = if record.skips("cross-seed-seedbox", "announce") {
+            Ok(StatusCode::OK)
         } else {
-            warn!("[/announce] Error returned from cross-seed seedbox API");
+            cross_seed_announce(
+                cross_seed_url,
+                cross_seed_api_key,
+                &(announce.clone().into()),
+            )
+            .await
+        };
+
+        match result {
+            Ok(status_code) => {
+                let accepted = status_code.as_u16() == 200;
+                record.call("cross-seed-seedbox", "announce", accepted, status_code);
+                if accepted {
+                    info!("Release {name} accepted by cross-seed-seedbox.");
+                    any_success = true;
+                }
+            }
+            Err(err) => {
+                record.call("cross-seed-seedbox", "announce", false, &err);
+                warn!("Error returned from cross-seed seedbox API");
+            }
         }
     }
 
@@ -84,33 +112,126 @@ async fn do_announce(state: Arc<RwLock<AppState>>, announce: Announce) -> anyhow
         let cross_seed_api_key =
             cross_seed_local_api_key.context("No API key for cross-seed local found.")?;
 
-        if let Ok(status_code) = cross_seed_announce(
-            cross_seed_url,
-            cross_seed_api_key,
-            &(announce.clone().into()),
-        )
-        .await
-        {
-            if status_code.as_u16() == 200 {
-                info!("[/announce] Release {name} accepted by cross-seed-seedbox.");
-                any_success = true;
-            }
+        let result = if record.skips("cross-seed-local", "announce") {
+            Ok(StatusCode::OK)
         } else {
-            warn!("[/announce] Error returned from cross-seed seedbox API");
+            cross_seed_announce(
+                cross_seed_url,
+                cross_seed_api_key,
+                &(announce.clone().into()),
+            )
+            .await
+        };
+
+        match result {
+            Ok(status_code) => {
+                let accepted = status_code.as_u16() == 200;
+                record.call("cross-seed-local", "announce", accepted, status_code);
+                if accepted {
+                    info!("Release {name} accepted by cross-seed-local.");
+                    any_success = true;
+                }
+            }
+            Err(err) => {
+                record.call("cross-seed-local", "announce", false, &err);
+                warn!("Error returned from cross-seed local API");
+            }
         }
     }
 
     Ok(any_success)
 }
 
+fn announce_fields(announce: &Announce) -> EntryFields {
+    EntryFields {
+        release_title: Some(announce.name.clone()),
+        ..Default::default()
+    }
+}
+
+/// Announce a release to all cross-seed instances, finishing its history entry `record`.
+/// Returns whether any instance accepted it.
+async fn run_announce(
+    state: &Arc<RwLock<AppState>>,
+    announce: Announce,
+    record: Record,
+) -> anyhow::Result<bool> {
+    let job = {
+        let state = state.clone();
+        let record = record.clone();
+        async move {
+            let release = Release {
+                release_title: Some(announce.name.clone()),
+                indexer: Some(announce.tracker.clone()),
+                ..Default::default()
+            };
+
+            let result = do_announce(state.clone(), announce, &record).await;
+            let event = match &result {
+                Ok(true) => {
+                    record.finish_with(Status::Success, None);
+                    Event::new(EventKind::AnnounceAccepted, release)
+                }
+                Ok(false) => {
+                    record.finish_with(
+                        Status::Failed,
+                        Some("Release was not accepted by any cross-seed instance.".to_string()),
+                    );
+                    Event::new(EventKind::AnnounceRejected, release)
+                }
+                Err(err) => {
+                    record.finish_with(Status::Failed, Some(err.to_string()));
+                    Event::new(EventKind::AnnounceRejected, release).with_error(err)
+                }
+            };
+            notify(&state, &record, event);
+
+            result
+        }
+    };
+
+    run_job(state, record.id(), job).await
+}
+
+/// Announce a single release from the command line.
+pub(crate) async fn announce_command(
+    state: Arc<RwLock<AppState>>,
+    announce: Announce,
+) -> anyhow::Result<()> {
+    let name = announce.name.clone();
+    let fields = announce_fields(&announce);
+    let record = db(&state)?.start(
+        "/announce",
+        &Origin::command_line(&state),
+        fields,
+        &announce,
+    )?;
+
+    if !run_announce(&state, announce, record).await? {
+        bail!("Release {name} was not accepted by any cross-seed instance.");
+    }
+
+    info!("Release {name} was accepted.");
+    Ok(())
+}
+
 pub(crate) async fn announce(
     State(state): State<Arc<RwLock<AppState>>>,
+    origin: Origin,
     Json(payload): Json<Announce>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    match do_announce(state, payload).await {
+    let record = record_request(
+        &state,
+        "/announce",
+        &origin,
+        announce_fields(&payload),
+        &payload,
+    );
+
+    match run_announce(&state, payload, record.clone()).await {
         Ok(success) => {
             if success {
-                Ok(StatusCode::OK)
+                Ok(finished_response(&record))
             } else {
                 error!("Failed to handle request correctly.");
                 Err(StatusCode::BAD_REQUEST)
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use reqwest::Client;
+
+/// Entry of the Sonarr / Radarr history API.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HistoryRecord {
+    pub source_title: String,
+    pub download_id: Option<String>,
+    pub date: String,
+    #[serde(default)]
+    pub data: HashMap<String, Value>,
+}
+
+impl HistoryRecord {
+    /// Value of `key` in the event specific data, if it is a non-empty string.
+    pub(crate) fn data(&self, key: &str) -> Option<String> {
+        self.data
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    }
+}
+
+/// All imports of downloads since `since`, oldest first. Works for both Sonarr and Radarr.
+pub(crate) async fn arr_imports_since(
+    arr_url: &str,
+    arr_api_key: &str,
+    since: SystemTime,
+) -> anyhow::Result<Vec<HistoryRecord>> {
+    let client = Client::new();
+    let response = client
+        .get(format!("{arr_url}/api/v3/history/since"))
+        .header("Accept", "application/json")
+        .header("X-Api-Key", arr_api_key)
+        .query(&[
+            ("date", humantime::format_rfc3339_seconds(since).to_string()),
+            ("eventType", "downloadFolderImported".to_string()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let mut records = response.json::<Vec<HistoryRecord>>().await?;
+    records.sort_by(|a, b| a.date.cmp(&b.date));
+
+    Ok(records)
+}
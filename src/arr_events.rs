@@ -0,0 +1,431 @@
+use std::env;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use tracing::{info, warn};
+
+use anyhow::{anyhow, bail, Context};
+
+use qbit_rs::{
+    model::{Credential, GetTorrentListArg},
+    Qbit,
+};
+
+use crate::arr_instances::arr_instance;
+use crate::auth::env_flag;
+use crate::data_types::RemovedFile;
+use crate::db::{now, Record};
+use crate::notify::Release;
+use crate::AppState;
+
+/// Grabs without an import are forgotten after a week.
+const GRAB_RETENTION: i64 = 7 * 24 * 60 * 60;
+
+/// What happens to the torrents of files replaced by an upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum CleanupAction {
+    #[default]
+    Keep,
+    Remove,
+    /// Add the tag `ARR_UPGRADE_TAG` to clean up by hand
+    Tag,
+}
+
+impl CleanupAction {
+    pub(crate) fn parse(action: &str) -> anyhow::Result<Self> {
+        match action.trim().to_lowercase().as_str() {
+            "keep" | "off" | "" => Ok(CleanupAction::Keep),
+            "remove" => Ok(CleanupAction::Remove),
+            "tag" => Ok(CleanupAction::Tag),
+            _ => bail!("Unknown cleanup action {action}, use keep, remove or tag"),
+        }
+    }
+}
+
+/// `name` as part of an environment variable, e.g. `Sonarr 4K` -> `SONARR_4K`.
+pub(crate) fn env_key(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// How Sonarr / Radarr events besides imports are handled, all disabled by default.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ArrEventOptions {
+    /// Remember the release of Grab events to complete the details of the import
+    pub preregister_grabs: bool,
+    /// Cleanup of the torrents of files replaced by an upgrade, instances can override it with
+    /// `ARR_INSTANCE_<NAME>_UPGRADE_CLEANUP`
+    pub upgrade_cleanup: CleanupAction,
+    pub upgrade_tag: String,
+    /// Remove the torrents of deleted files from the local qbittorrent
+    pub file_delete_remove_orphans: bool,
+    /// Also delete the data of removed torrents
+    pub delete_files: bool,
+}
+
+impl ArrEventOptions {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let upgrade_cleanup = match env::var("ARR_UPGRADE_CLEANUP") {
+            Ok(action) => CleanupAction::parse(&action).context("Invalid ARR_UPGRADE_CLEANUP")?,
+            Err(_) => CleanupAction::Keep,
+        };
+
+        Ok(ArrEventOptions {
+            preregister_grabs: env_flag("ARR_GRAB_PREREGISTER", false)?,
+            upgrade_cleanup,
+            upgrade_tag: env::var("ARR_UPGRADE_TAG").unwrap_or("replaced".to_string()),
+            file_delete_remove_orphans: env_flag("ARR_FILE_DELETE_REMOVE_ORPHANS", false)?,
+            delete_files: env_flag("ARR_REMOVE_DELETE_FILES", false)?,
+        })
+    }
+}
+
+fn options(state: &Arc<RwLock<AppState>>) -> anyhow::Result<ArrEventOptions> {
+    let read_guard = state
+        .read()
+        .map_err(|_| anyhow!("Could not read from state."))?;
+
+    Ok(read_guard.arr_event_options.clone())
+}
+
+/// Remember the release of a Grab event by its download id, if enabled.
+pub(crate) fn grabbed(state: &Arc<RwLock<AppState>>, release: Release) -> anyhow::Result<()> {
+    if !options(state)?.preregister_grabs {
+        info!("Grab event ignored.");
+        return Ok(());
+    }
+
+    let download_id = release
+        .download_id
+        .clone()
+        .context("Grab does not include a download_id.")?;
+
+    let mut write_guard = state
+        .write()
+        .map_err(|_| anyhow!("Could not write to state."))?;
+    write_guard
+        .grabs
+        .retain(|_, (grabbed_at, _)| *grabbed_at > now() - GRAB_RETENTION);
+    write_guard
+        .grabs
+        .insert(download_id.clone(), (now(), release));
+
+    info!("Registered grab of {download_id}.");
+    Ok(())
+}
+
+/// Complete `release` with the details of its grab, if it was registered.
+pub(crate) fn with_grab(state: &Arc<RwLock<AppState>>, mut release: Release) -> Release {
+    let Some(download_id) = &release.download_id else {
+        return release;
+    };
+
+    if let Ok(read_guard) = state.read() {
+        if let Some((_, grab)) = read_guard.grabs.get(download_id) {
+            release.complete_from(grab);
+        }
+    }
+    release
+}
+
+/// Remove or tag the torrents of the files an upgrade of `instance` replaced, as configured.
+pub(crate) async fn upgraded(
+    state: &Arc<RwLock<AppState>>,
+    instance: Option<&str>,
+    files: &[RemovedFile],
+    record: &Record,
+) -> anyhow::Result<()> {
+    let options = options(state)?;
+    let cleanup = arr_instance(state, instance)?
+        .upgrade_cleanup
+        .unwrap_or(options.upgrade_cleanup);
+
+    match cleanup {
+        CleanupAction::Keep => Ok(()),
+        CleanupAction::Remove => {
+            remove_torrents(state, instance, files, options.delete_files, record).await
+        }
+        CleanupAction::Tag => {
+            tag_torrents(state, instance, files, &options.upgrade_tag, record).await
+        }
+    }
+}
+
+/// Remove the torrents of files deleted in `instance`, if enabled.
+pub(crate) async fn files_deleted(
+    state: &Arc<RwLock<AppState>>,
+    instance: Option<&str>,
+    files: &[RemovedFile],
+    record: &Record,
+) -> anyhow::Result<()> {
+    let options = options(state)?;
+    if !options.file_delete_remove_orphans {
+        info!("File delete event ignored.");
+        return Ok(());
+    }
+
+    remove_torrents(state, instance, files, options.delete_files, record).await
+}
+
+/// Extensions single file torrents are named with besides the release name.
+const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "m4v", "ts", "wmv"];
+
+/// How sure it is that a torrent is the one a file was imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Match {
+    /// Only the file names are the same, e.g. `S01E01.mkv`, which is too weak to remove it
+    FileName,
+    /// Named after the release or its content is the file
+    Exact,
+}
+
+/// Whether the torrent called `name` with `content_path` is the one `file` was imported from.
+fn matches(name: Option<&str>, content_path: Option<&str>, file: &RemovedFile) -> Option<Match> {
+    let by_name = file
+        .scene_name
+        .as_deref()
+        .filter(|scene_name| !scene_name.is_empty())
+        .zip(name)
+        .is_some_and(|(scene_name, name)| {
+            // single file torrents are named after the file
+            name == scene_name
+                || name.rsplit_once('.').is_some_and(|(stem, extension)| {
+                    stem == scene_name
+                        && VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                })
+        });
+
+    let (Some(path), Some(content_path)) = (file.path.as_deref(), content_path) else {
+        return by_name.then_some(Match::Exact);
+    };
+    let (path, content_path) = (Path::new(path), Path::new(content_path));
+
+    if by_name || path == content_path {
+        Some(Match::Exact)
+    } else if path.file_name().is_some() && path.file_name() == content_path.file_name() {
+        // the path differs if the library is mounted elsewhere, the file name doesn't
+        Some(Match::FileName)
+    } else {
+        None
+    }
+}
+
+/// Hashes of the torrents in the local qbittorrent `files` were imported from, matched at least
+/// as sure as `required`.
+async fn find_torrents(
+    qbit: &Qbit,
+    files: &[RemovedFile],
+    required: Match,
+) -> anyhow::Result<Vec<String>> {
+    let torrents = qbit.get_torrent_list(GetTorrentListArg::default()).await?;
+
+    Ok(torrents
+        .iter()
+        .filter(|torrent| {
+            files.iter().any(|file| {
+                let name = torrent.name.as_deref();
+                matches(name, torrent.content_path.as_deref(), file) >= Some(required)
+            })
+        })
+        .filter_map(|torrent| torrent.hash.clone())
+        .collect())
+}
+
+/// The local qbittorrent of `instance`.
+fn qbit_local(state: &Arc<RwLock<AppState>>, instance: Option<&str>) -> anyhow::Result<Qbit> {
+    let settings = arr_instance(state, instance)?;
+
+    let host = settings
+        .qbittorrent_local_host
+        .context("QBITTORRENT_LOCAL_HOST is not set.")?;
+    let user = settings
+        .qbittorrent_local_user
+        .context("QBITTORRENT_LOCAL_USER is not set.")?;
+    let password = settings
+        .qbittorrent_local_password
+        .context("QBITTORRENT_LOCAL_PASSWORD is not set.")?;
+
+    Ok(Qbit::new(
+        reqwest::Url::parse(&host)?,
+        Credential::new(user, password),
+    ))
+}
+
+/// Remove the torrents `files` were imported from from the local qbittorrent of `instance`.
+async fn remove_torrents(
+    state: &Arc<RwLock<AppState>>,
+    instance: Option<&str>,
+    files: &[RemovedFile],
+    delete_files: bool,
+    record: &Record,
+) -> anyhow::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let qbit_local = qbit_local(state, instance)?;
+    // removing, possibly with the data, needs an exact match
+    let hashes = find_torrents(&qbit_local, files, Match::Exact).await?;
+    if hashes.is_empty() {
+        info!("No torrents found for the {} removed files.", files.len());
+        return Ok(());
+    }
+
+    let removed = if record.skips("qbittorrent-local", "remove torrent") {
+        Ok(())
+    } else {
+        qbit_local
+            .delete_torrents(hashes.clone(), delete_files)
+            .await
+    };
+    match &removed {
+        Ok(_) => record.call(
+            "qbittorrent-local",
+            "remove torrent",
+            true,
+            hashes.join(", "),
+        ),
+        Err(err) => {
+            warn!("Could not remove torrents {}: {err}", hashes.join(", "));
+            record.call("qbittorrent-local", "remove torrent", false, err)
+        }
+    }
+    removed?;
+
+    info!("Removed {} torrents from qbittorrent-local.", hashes.len());
+    Ok(())
+}
+
+/// Tag the torrents `files` were imported from in the local qbittorrent of `instance`.
+async fn tag_torrents(
+    state: &Arc<RwLock<AppState>>,
+    instance: Option<&str>,
+    files: &[RemovedFile],
+    tag: &str,
+    record: &Record,
+) -> anyhow::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let qbit_local = qbit_local(state, instance)?;
+    // tags are checked by hand, the file name is enough
+    let hashes = find_torrents(&qbit_local, files, Match::FileName).await?;
+    if hashes.is_empty() {
+        info!("No torrents found for the {} removed files.", files.len());
+        return Ok(());
+    }
+
+    let tagged = if record.skips("qbittorrent-local", "tag torrent") {
+        Ok(())
+    } else {
+        qbit_local
+            .add_tags(hashes.clone(), vec![tag.to_string()])
+            .await
+    };
+    match &tagged {
+        Ok(_) => record.call("qbittorrent-local", "tag torrent", true, hashes.join(", ")),
+        Err(err) => {
+            warn!("Could not tag torrents {}: {err}", hashes.join(", "));
+            record.call("qbittorrent-local", "tag torrent", false, err)
+        }
+    }
+    tagged?;
+
+    info!(
+        "Tagged {} torrents in qbittorrent-local with {tag}.",
+        hashes.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: Option<&str>, scene_name: Option<&str>) -> RemovedFile {
+        RemovedFile {
+            path: path.map(str::to_string),
+            scene_name: scene_name.map(str::to_string),
+        }
+    }
+
+    const SCENE_NAME: &str = "The.Bear.S01E01.720p.HDTV.x264-TEPES";
+    const PATH: &str = "/tv/The Bear/Season 1/The.Bear.S01E01.720p.HDTV.x264-TEPES.mkv";
+
+    #[test]
+    fn matches_release_name() {
+        let file = file(None, Some(SCENE_NAME));
+
+        assert_eq!(matches(Some(SCENE_NAME), None, &file), Some(Match::Exact));
+        let single_file = format!("{SCENE_NAME}.mkv");
+        assert_eq!(matches(Some(&single_file), None, &file), Some(Match::Exact));
+    }
+
+    #[test]
+    fn release_name_near_misses() {
+        let file = file(None, Some(SCENE_NAME));
+
+        let proper = format!("{SCENE_NAME}.PROPER");
+        assert_eq!(matches(Some(&proper), None, &file), None);
+        assert_eq!(matches(Some("The.Bear.S01E01"), None, &file), None);
+        assert_eq!(matches(Some(""), None, &file), None);
+        assert_eq!(matches(None, None, &file), None);
+        // an empty scene name matches nothing
+        assert_eq!(matches(Some(""), None, &self::file(None, Some(""))), None);
+    }
+
+    #[test]
+    fn matches_content_path() {
+        let file = file(Some(PATH), None);
+
+        assert_eq!(
+            matches(Some("other"), Some(PATH), &file),
+            Some(Match::Exact)
+        );
+        let mounted_elsewhere = "/data/torrent/tv/The.Bear.S01E01.720p.HDTV.x264-TEPES.mkv";
+        assert_eq!(
+            matches(Some("other"), Some(mounted_elsewhere), &file),
+            Some(Match::FileName)
+        );
+    }
+
+    #[test]
+    fn file_name_only_is_not_exact() {
+        let file = file(
+            Some("/tv/Show A/Season 1/S01E01.mkv"),
+            Some("Show.A.S01E01"),
+        );
+
+        let other_show = matches(
+            Some("Show.B.S01E01"),
+            Some("/data/torrent/Show B/S01E01.mkv"),
+            &file,
+        );
+        assert_eq!(other_show, Some(Match::FileName));
+        assert!(other_show < Some(Match::Exact));
+
+        // a folder of a season pack is not the file
+        let season_pack = matches(Some("Show.A.S01"), Some("/data/torrent/Show.A.S01"), &file);
+        assert_eq!(season_pack, None);
+    }
+
+    #[test]
+    fn parse_cleanup_action() {
+        assert_eq!(CleanupAction::parse("").unwrap(), CleanupAction::Keep);
+        assert_eq!(CleanupAction::parse("off").unwrap(), CleanupAction::Keep);
+        assert_eq!(
+            CleanupAction::parse(" Remove ").unwrap(),
+            CleanupAction::Remove
+        );
+        assert_eq!(CleanupAction::parse("TAG").unwrap(), CleanupAction::Tag);
+        assert!(CleanupAction::parse("delete").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+
+use tracing::{info, trace, warn};
+
+use anyhow::{anyhow, Context};
+
+use axum::http::StatusCode;
+
+use crate::arr_events::{env_key, CleanupAction};
+use crate::AppState;
+
+/// Comma separated list of download client names in `var`.
+pub(crate) fn clients_from_env(var: &str) -> Option<Vec<String>> {
+    env::var(var)
+        .ok()
+        .map(|clients| clients.split(',').map(|s| s.trim().to_string()).collect())
+}
+
+/// Settings used for the requests of a Sonarr / Radarr instance. The ones of a profile override
+/// the global settings, everything not set falls back to them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ArrInstance {
+    pub cross_seed_local_url: Option<String>,
+    pub cross_seed_local_api_key: Option<String>,
+
+    pub xseed_torrent_clients: Option<Vec<String>>,
+    pub xseed_usenet_clients: Option<Vec<String>>,
+
+    pub qbittorrent_local_host: Option<String>,
+    pub qbittorrent_local_user: Option<String>,
+    pub qbittorrent_local_password: Option<String>,
+
+    pub qbittorrent_local_dir: Option<String>,
+    pub qbittorrent_seedbox_name: Option<String>,
+
+    /// Cleanup of the torrents of files replaced by an upgrade, see `ARR_UPGRADE_CLEANUP`
+    pub upgrade_cleanup: Option<CleanupAction>,
+}
+
+impl ArrInstance {
+    /// Reads the same variables as the global settings, prefixed with `prefix`.
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let var = |name: &str| env::var(format!("{prefix}_{name}")).ok();
+
+        let upgrade_cleanup = var("UPGRADE_CLEANUP")
+            .map(|action| {
+                CleanupAction::parse(&action)
+                    .with_context(|| format!("Invalid {prefix}_UPGRADE_CLEANUP"))
+            })
+            .transpose()?;
+
+        Ok(ArrInstance {
+            cross_seed_local_url: var("CROSS_SEED_LOCAL_URL"),
+            cross_seed_local_api_key: var("CROSS_SEED_LOCAL_API_KEY"),
+
+            xseed_torrent_clients: clients_from_env(&format!("{prefix}_XSEED_TORRENT_CLIENTS")),
+            xseed_usenet_clients: clients_from_env(&format!("{prefix}_XSEED_USENET_CLIENTS")),
+
+            qbittorrent_local_host: var("QBITTORRENT_LOCAL_HOST"),
+            qbittorrent_local_user: var("QBITTORRENT_LOCAL_USER"),
+            qbittorrent_local_password: var("QBITTORRENT_LOCAL_PASSWORD"),
+
+            qbittorrent_local_dir: var("QBITTORRENT_LOCAL_DIR"),
+            qbittorrent_seedbox_name: var("QBITTORRENT_SEEDBOX_NAME"),
+
+            upgrade_cleanup,
+        })
+    }
+
+    /// The global settings.
+    fn global(state: &AppState) -> Self {
+        ArrInstance {
+            cross_seed_local_url: state.cross_seed_local_url.clone(),
+            cross_seed_local_api_key: state.cross_seed_local_api_key.clone(),
+
+            xseed_torrent_clients: state.xseed_torrent_clients.clone(),
+            xseed_usenet_clients: state.xseed_usenet_clients.clone(),
+
+            qbittorrent_local_host: state.qbittorrent_local_host.clone(),
+            qbittorrent_local_user: state.qbittorrent_local_user.clone(),
+            qbittorrent_local_password: state.qbittorrent_local_password.clone(),
+
+            qbittorrent_local_dir: state.qbittorrent_local_dir.clone(),
+            qbittorrent_seedbox_name: state.qbittorrent_seedbox_name.clone(),
+
+            upgrade_cleanup: Some(state.arr_event_options.upgrade_cleanup),
+        }
+    }
+
+    /// `self` with everything it doesn't set taken from `defaults`.
+    fn or(self, defaults: ArrInstance) -> Self {
+        ArrInstance {
+            cross_seed_local_url: self.cross_seed_local_url.or(defaults.cross_seed_local_url),
+            cross_seed_local_api_key: self
+                .cross_seed_local_api_key
+                .or(defaults.cross_seed_local_api_key),
+
+            xseed_torrent_clients: self
+                .xseed_torrent_clients
+                .or(defaults.xseed_torrent_clients),
+            xseed_usenet_clients: self.xseed_usenet_clients.or(defaults.xseed_usenet_clients),
+
+            qbittorrent_local_host: self
+                .qbittorrent_local_host
+                .or(defaults.qbittorrent_local_host),
+            qbittorrent_local_user: self
+                .qbittorrent_local_user
+                .or(defaults.qbittorrent_local_user),
+            qbittorrent_local_password: self
+                .qbittorrent_local_password
+                .or(defaults.qbittorrent_local_password),
+
+            qbittorrent_local_dir: self
+                .qbittorrent_local_dir
+                .or(defaults.qbittorrent_local_dir),
+            qbittorrent_seedbox_name: self
+                .qbittorrent_seedbox_name
+                .or(defaults.qbittorrent_seedbox_name),
+
+            upgrade_cleanup: self.upgrade_cleanup.or(defaults.upgrade_cleanup),
+        }
+    }
+}
+
+/// Read the instance profiles from the environment. `ARR_INSTANCES` lists the names of the
+/// instances, each one is configured with `ARR_INSTANCE_<NAME>_` followed by the name of a global
+/// setting, e.g. `ARR_INSTANCE_SONARR_4K_CROSS_SEED_LOCAL_URL`.
+pub(crate) fn arr_instances_from_env() -> anyhow::Result<HashMap<String, ArrInstance>> {
+    let mut instances = HashMap::new();
+
+    if let Ok(names) = env::var("ARR_INSTANCES") {
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let key = env_key(name);
+            instances.insert(
+                key.clone(),
+                ArrInstance::from_env(&format!("ARR_INSTANCE_{key}"))?,
+            );
+            info!("Loaded settings for instance {name}.");
+        }
+    }
+
+    Ok(instances)
+}
+
+/// Settings of the instance called `name` in `instances`, `global` for instances without a
+/// profile.
+fn lookup(
+    instances: &HashMap<String, ArrInstance>,
+    global: ArrInstance,
+    name: Option<&str>,
+) -> ArrInstance {
+    let Some(name) = name else {
+        return global;
+    };
+
+    match instances.get(&env_key(name)) {
+        Some(instance) => {
+            trace!("Using the settings of instance {name}.");
+            instance.clone().or(global)
+        }
+        // without any profiles every instance uses the global settings on purpose
+        None if instances.is_empty() => global,
+        None => {
+            warn!("No settings for instance {name} in ARR_INSTANCES, using the global ones.");
+            global
+        }
+    }
+}
+
+/// Settings for requests of the instance called `name`, the global ones for instances without a
+/// profile.
+pub(crate) fn arr_instance(
+    state: &Arc<RwLock<AppState>>,
+    name: Option<&str>,
+) -> anyhow::Result<ArrInstance> {
+    let read_guard = state
+        .read()
+        .map_err(|_| anyhow!("Could not read from state."))?;
+
+    Ok(lookup(
+        &read_guard.arr_instances,
+        ArrInstance::global(&read_guard),
+        name,
+    ))
+}
+
+/// Instance named in the route of a request, e.g. `/xseed-sonarr/{instance}`. Only instances
+/// listed in `ARR_INSTANCES` have a route, others are not found instead of silently using the
+/// global settings.
+pub(crate) fn route_instance(
+    state: &Arc<RwLock<AppState>>,
+    instance: Option<String>,
+) -> Result<Option<String>, StatusCode> {
+    let Some(instance) = instance else {
+        return Ok(None);
+    };
+
+    let known = state
+        .read()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .arr_instances
+        .contains_key(&env_key(&instance));
+    if !known {
+        warn!("No settings for instance {instance}, it is not listed in ARR_INSTANCES.");
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Some(instance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> ArrInstance {
+        ArrInstance {
+            upgrade_cleanup: Some(CleanupAction::Keep),
+            cross_seed_local_url: Some("http://cross-seed:2468".to_string()),
+            cross_seed_local_api_key: Some("global-key".to_string()),
+            xseed_torrent_clients: Some(vec!["qbittorrent".to_string()]),
+            qbittorrent_local_dir: Some("/data/torrent".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn four_k() -> ArrInstance {
+        ArrInstance {
+            cross_seed_local_url: Some("http://cross-seed-4k:2468".to_string()),
+            xseed_torrent_clients: Some(vec!["qbittorrent-4k".to_string()]),
+            upgrade_cleanup: Some(CleanupAction::Tag),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_instances_from_env() {
+        env::set_var("ARR_INSTANCES", "Sonarr 4K, radarr-4k,,");
+        env::set_var(
+            "ARR_INSTANCE_SONARR_4K_CROSS_SEED_LOCAL_URL",
+            "http://cross-seed-4k:2468",
+        );
+        env::set_var(
+            "ARR_INSTANCE_SONARR_4K_XSEED_TORRENT_CLIENTS",
+            "qbittorrent-4k, qbittorrent-4k-2",
+        );
+        env::set_var("ARR_INSTANCE_RADARR_4K_QBITTORRENT_LOCAL_DIR", "/data/4k");
+        env::set_var("ARR_INSTANCE_RADARR_4K_UPGRADE_CLEANUP", "Remove");
+
+        let instances = arr_instances_from_env().unwrap();
+        env::remove_var("ARR_INSTANCES");
+
+        env::set_var("ARR_INSTANCE_INVALID_UPGRADE_CLEANUP", "delete");
+        assert!(ArrInstance::from_env("ARR_INSTANCE_INVALID").is_err());
+
+        let mut names = instances.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["RADARR_4K", "SONARR_4K"]);
+
+        let sonarr = &instances["SONARR_4K"];
+        assert_eq!(
+            sonarr.cross_seed_local_url.as_deref(),
+            Some("http://cross-seed-4k:2468")
+        );
+        assert_eq!(
+            sonarr.xseed_torrent_clients.as_deref(),
+            Some(&["qbittorrent-4k".to_string(), "qbittorrent-4k-2".to_string()][..])
+        );
+        assert!(sonarr.qbittorrent_local_dir.is_none());
+
+        let radarr = &instances["RADARR_4K"];
+        assert_eq!(radarr.qbittorrent_local_dir.as_deref(), Some("/data/4k"));
+        assert_eq!(radarr.upgrade_cleanup, Some(CleanupAction::Remove));
+        assert!(sonarr.upgrade_cleanup.is_none());
+        assert!(radarr.cross_seed_local_url.is_none());
+    }
+
+    #[test]
+    fn profile_overrides_global() {
+        let merged = four_k().or(global());
+
+        assert_eq!(
+            merged.cross_seed_local_url.as_deref(),
+            Some("http://cross-seed-4k:2468")
+        );
+        assert_eq!(
+            merged.xseed_torrent_clients,
+            Some(vec!["qbittorrent-4k".to_string()])
+        );
+        // not overridden, falls back
+        assert_eq!(
+            merged.cross_seed_local_api_key.as_deref(),
+            Some("global-key")
+        );
+        assert_eq!(
+            merged.qbittorrent_local_dir.as_deref(),
+            Some("/data/torrent")
+        );
+        assert!(merged.qbittorrent_local_host.is_none());
+        assert_eq!(merged.upgrade_cleanup, Some(CleanupAction::Tag));
+
+        let merged = ArrInstance::default().or(global());
+        assert_eq!(merged.upgrade_cleanup, Some(CleanupAction::Keep));
+    }
+
+    #[test]
+    fn looks_up_instances() {
+        let instances = HashMap::from([("SONARR_4K".to_string(), four_k())]);
+        let url = |name: Option<&str>| lookup(&instances, global(), name).cross_seed_local_url;
+
+        assert_eq!(
+            url(Some("Sonarr 4K")).as_deref(),
+            Some("http://cross-seed-4k:2468")
+        );
+        assert_eq!(
+            url(Some("sonarr-4k")).as_deref(),
+            Some("http://cross-seed-4k:2468")
+        );
+        // unknown and missing names use the global settings
+        assert_eq!(
+            url(Some("Sonarr 4k HDR")).as_deref(),
+            Some("http://cross-seed:2468")
+        );
+        assert_eq!(
+            url(Some("Sonarr")).as_deref(),
+            Some("http://cross-seed:2468")
+        );
+        assert_eq!(url(None).as_deref(), Some("http://cross-seed:2468"));
+
+        let none = HashMap::new();
+        assert_eq!(
+            lookup(&none, global(), Some("Sonarr"))
+                .cross_seed_local_url
+                .as_deref(),
+            Some("http://cross-seed:2468")
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use tracing::{info, warn};
+
+use anyhow::Context;
+
+use ipnet::IpNet;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use super::{peer_ip, Scope, ALL_SCOPES};
+use crate::AppState;
+
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// IP of the client after resolving `X-Forwarded-For` of trusted proxies, available to handlers
+/// as an `Extension`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClientIp(pub IpAddr);
+
+/// Networks allowed to access each group of routes, and the reverse proxies whose
+/// `X-Forwarded-For` header is trusted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AllowList {
+    /// Groups without an entry are open to everyone
+    networks: HashMap<Scope, Vec<IpNet>>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+/// Parse a comma-separated list of networks in CIDR notation. Single IPs are accepted as well.
+fn parse_networks(networks: &str) -> anyhow::Result<Vec<IpNet>> {
+    networks
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .with_context(|| format!("Invalid network {network}"))
+        })
+        .collect()
+}
+
+fn contains(networks: &[IpNet], ip: IpAddr) -> bool {
+    // IPv4 clients of a dual stack listener show up as IPv4-mapped IPv6 addresses
+    let ip = match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip.into()),
+        ip => ip,
+    };
+
+    networks.iter().any(|network| network.contains(&ip))
+}
+
+impl AllowList {
+    /// Read the allowlists from the environment. `ALLOWED_IPS_<SCOPE>` restricts a group of routes,
+    /// `ALLOWED_IPS` applies to all groups without their own list. `TRUSTED_PROXIES` lists the
+    /// reverse proxies allowed to set `X-Forwarded-For`.
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let default = env::var("ALLOWED_IPS")
+            .ok()
+            .map(|networks| parse_networks(&networks).context("Invalid ALLOWED_IPS"))
+            .transpose()?;
+
+        let mut allow_list = AllowList::default();
+
+        for scope in ALL_SCOPES {
+            let var = format!("ALLOWED_IPS_{}", scope.as_str().to_uppercase());
+            let networks = match env::var(&var) {
+                Ok(networks) => {
+                    parse_networks(&networks).with_context(|| format!("Invalid {var}"))?
+                }
+                Err(_) => match &default {
+                    Some(networks) => networks.clone(),
+                    None => continue,
+                },
+            };
+
+            info!("Access to {} is allowed from {networks:?}", scope.as_str());
+            allow_list.networks.insert(*scope, networks);
+        }
+
+        if let Ok(proxies) = env::var("TRUSTED_PROXIES") {
+            allow_list.trusted_proxies =
+                parse_networks(&proxies).context("Invalid TRUSTED_PROXIES")?;
+        }
+
+        Ok(allow_list)
+    }
+
+    fn allows(&self, scope: Scope, ip: IpAddr) -> bool {
+        self.networks
+            .get(&scope)
+            .is_none_or(|networks| contains(networks, ip))
+    }
+
+    /// Resolve the IP of the client. `X-Forwarded-For` is only followed as long as the request
+    /// came through trusted proxies, the first untrusted address is the client.
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !contains(&self.trusted_proxies, peer) {
+            return peer;
+        }
+
+        let forwarded = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|ip| ip.trim().parse::<IpAddr>())
+            .collect::<Vec<_>>();
+
+        let mut client = peer;
+        for ip in forwarded.into_iter().rev() {
+            let Ok(ip) = ip else {
+                // do not trust anything left of garbage
+                break;
+            };
+
+            client = ip;
+            if !contains(&self.trusted_proxies, ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// Middleware rejecting clients not allowed to access the requested group of routes.
+pub(crate) async fn allowlist_middleware(
+    State(state): State<Arc<RwLock<AppState>>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(peer) = peer_ip(&request) else {
+        return next.run(request).await;
+    };
+
+    let (ip, allowed) = {
+        let read_guard = state.read().unwrap();
+        let allow_list = &read_guard.allow_list;
+
+        let ip = allow_list.client_ip(peer, request.headers());
+        let allowed =
+            Scope::of_path(request.uri().path()).is_none_or(|scope| allow_list.allows(scope, ip));
+
+        (ip, allowed)
+    };
+
+    if !allowed {
+        warn!(
+            "Rejected request to {} from {ip}, not in allowlist",
+            request.uri().path()
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    request.extensions_mut().insert(ClientIp(ip));
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn behind_proxies(proxies: &str) -> AllowList {
+        AllowList {
+            trusted_proxies: parse_networks(proxies).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(FORWARDED_FOR_HEADER, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn parses_networks() {
+        let networks = parse_networks(" 10.0.0.0/8, 192.168.1.5 ,,fd00::/8").unwrap();
+        assert_eq!(networks.len(), 3);
+        assert!(contains(&networks, ip("10.1.2.3")));
+        assert!(contains(&networks, ip("192.168.1.5")));
+        assert!(!contains(&networks, ip("192.168.1.6")));
+        assert!(contains(&networks, ip("fd12::1")));
+
+        assert!(parse_networks("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_networks() {
+        assert!(parse_networks("10.0.0.0/33").is_err());
+        assert!(parse_networks("10.0.0.0/8, nonsense").is_err());
+        assert!(parse_networks("300.0.0.1").is_err());
+        assert!(parse_networks("fd00::/129").is_err());
+    }
+
+    #[test]
+    fn contains_ipv4_mapped_ipv6() {
+        let networks = parse_networks("192.168.0.0/16").unwrap();
+        assert!(contains(&networks, ip("::ffff:192.168.1.10")));
+        assert!(!contains(&networks, ip("::ffff:10.0.0.1")));
+        assert!(!contains(&networks, ip("fd00::1")));
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_for() {
+        let allow_list = behind_proxies("10.0.0.1");
+        let headers = forwarded_for(&["192.168.1.10"]);
+
+        assert_eq!(
+            allow_list.client_ip(ip("203.0.113.7"), &headers),
+            ip("203.0.113.7")
+        );
+        // nothing is trusted without TRUSTED_PROXIES
+        assert_eq!(
+            AllowList::default().client_ip(ip("10.0.0.1"), &headers),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn walks_forwarded_for_through_trusted_proxies() {
+        let allow_list = behind_proxies("10.0.0.0/24");
+
+        let headers = forwarded_for(&["203.0.113.7, 10.0.0.2"]);
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+
+        // multiple headers are one list
+        let headers = forwarded_for(&["203.0.113.7", "10.0.0.3, 10.0.0.2"]);
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+
+        // only trusted proxies in the chain
+        let headers = forwarded_for(&["10.0.0.3, 10.0.0.2"]);
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &headers),
+            ip("10.0.0.3")
+        );
+
+        // no header at all
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn ignores_spoofed_leftmost_entries() {
+        let allow_list = behind_proxies("10.0.0.0/24");
+
+        // the client claims to be on the local network, the proxy appended its real address
+        let headers = forwarded_for(&["192.168.1.10, 203.0.113.7"]);
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+
+        // the client claims to be a trusted proxy
+        let headers = forwarded_for(&["192.168.1.10, 10.0.0.2, 203.0.113.7"]);
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+
+        // garbage stops the walk at the last valid address
+        let headers = forwarded_for(&["192.168.1.10, garbage, 10.0.0.2"]);
+        assert_eq!(
+            allow_list.client_ip(ip("10.0.0.1"), &headers),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn trusts_ipv4_mapped_proxy() {
+        let allow_list = behind_proxies("10.0.0.1");
+        let headers = forwarded_for(&["203.0.113.7"]);
+
+        assert_eq!(
+            allow_list.client_ip(ip("::ffff:10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn allows_per_scope() {
+        let allow_list = AllowList {
+            networks: HashMap::from([(ALL_SCOPES[0], parse_networks("192.168.0.0/16").unwrap())]),
+            ..Default::default()
+        };
+
+        assert!(allow_list.allows(ALL_SCOPES[0], ip("192.168.1.10")));
+        assert!(allow_list.allows(ALL_SCOPES[0], ip("::ffff:192.168.1.10")));
+        assert!(!allow_list.allows(ALL_SCOPES[0], ip("203.0.113.7")));
+        // groups without an allowlist are open
+        for scope in &ALL_SCOPES[1..] {
+            assert!(allow_list.allows(*scope, ip("203.0.113.7")));
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use anyhow::Context;
+
+use serde::Serialize;
+
+use crate::db::now;
+
+/// After `max_failures` failed authentications within `window` a client is banned for
+/// `ban_duration`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BanConfig {
+    max_failures: u32,
+    window: Duration,
+    ban_duration: Duration,
+}
+
+pub(crate) fn env_duration(name: &str, default: Duration) -> anyhow::Result<Duration> {
+    match env::var(name) {
+        Ok(value) => humantime::parse_duration(&value).with_context(|| format!("Invalid {name}")),
+        Err(_) => Ok(default),
+    }
+}
+
+impl BanConfig {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let max_failures = match env::var("AUTH_MAX_FAILURES") {
+            Ok(value) => value.parse().context("Invalid AUTH_MAX_FAILURES")?,
+            Err(_) => 5,
+        };
+
+        Ok(BanConfig {
+            max_failures,
+            window: env_duration("AUTH_FAILURE_WINDOW", Duration::from_secs(10 * 60))?,
+            ban_duration: env_duration("AUTH_BAN_DURATION", Duration::from_secs(60 * 60))?,
+        })
+    }
+}
+
+struct Failures {
+    count: u32,
+    first: Instant,
+    banned_until: Option<Instant>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ClientStatus {
+    pub ip: IpAddr,
+    pub failures: u32,
+    /// Unix timestamp (seconds) the ban ends
+    pub banned_until: Option<i64>,
+}
+
+/// Failed authentications per client IP.
+pub(crate) struct AuthFailures {
+    config: BanConfig,
+    clients: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl AuthFailures {
+    pub(crate) fn new(config: BanConfig) -> Self {
+        AuthFailures {
+            config,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remaining ban duration of `ip`, if it is banned.
+    pub(crate) fn banned(&self, ip: IpAddr) -> Option<Duration> {
+        self.banned_at(ip, Instant::now())
+    }
+
+    fn banned_at(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let clients = self.clients.lock().ok()?;
+        let banned_until = clients.get(&ip)?.banned_until?;
+
+        banned_until
+            .checked_duration_since(now)
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Count a failed authentication of `ip`, banning it once the limit is reached.
+    pub(crate) fn failure(&self, ip: IpAddr) {
+        self.failure_at(ip, Instant::now());
+    }
+
+    fn failure_at(&self, ip: IpAddr, now: Instant) {
+        let Ok(mut clients) = self.clients.lock() else {
+            return;
+        };
+
+        let config = self.config;
+        clients.retain(|_, failures| {
+            now.duration_since(failures.first) < config.window
+                || failures.banned_until.is_some_and(|until| until > now)
+        });
+
+        let failures = clients.entry(ip).or_insert(Failures {
+            count: 0,
+            first: now,
+            banned_until: None,
+        });
+        // a client still failing after its ban ended is banned again
+        if failures.banned_until.is_some_and(|until| until <= now) {
+            failures.banned_until = None;
+        }
+        failures.count += 1;
+
+        if failures.count >= config.max_failures && failures.banned_until.is_none() {
+            failures.banned_until = Some(now + config.ban_duration);
+            warn!(
+                "Banned {ip} for {} after {} failed authentications",
+                humantime::format_duration(config.ban_duration),
+                failures.count
+            );
+        }
+    }
+
+    pub(crate) fn status(&self) -> Vec<ClientStatus> {
+        let Ok(clients) = self.clients.lock() else {
+            return Vec::new();
+        };
+
+        let now_instant = Instant::now();
+        let now_timestamp = now();
+        clients
+            .iter()
+            .map(|(ip, failures)| ClientStatus {
+                ip: *ip,
+                failures: failures.count,
+                banned_until: failures
+                    .banned_until
+                    .and_then(|until| until.checked_duration_since(now_instant))
+                    .map(|remaining| now_timestamp + remaining.as_secs() as i64),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn failures() -> AuthFailures {
+        AuthFailures::new(BanConfig {
+            max_failures: 3,
+            window: 10 * MINUTE,
+            ban_duration: 5 * MINUTE,
+        })
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 168, 1, last])
+    }
+
+    #[test]
+    fn bans_after_max_failures() {
+        let failures = failures();
+        let start = Instant::now();
+
+        failures.failure_at(ip(1), start);
+        failures.failure_at(ip(1), start + MINUTE);
+        assert_eq!(failures.banned_at(ip(1), start + MINUTE), None);
+
+        failures.failure_at(ip(1), start + 2 * MINUTE);
+        assert_eq!(
+            failures.banned_at(ip(1), start + 2 * MINUTE),
+            Some(5 * MINUTE)
+        );
+        // other clients are not affected
+        assert_eq!(failures.banned_at(ip(2), start + 2 * MINUTE), None);
+    }
+
+    #[test]
+    fn failures_outside_window_are_forgotten() {
+        let failures = failures();
+        let start = Instant::now();
+
+        failures.failure_at(ip(1), start);
+        failures.failure_at(ip(1), start + MINUTE);
+        failures.failure_at(ip(1), start + 11 * MINUTE);
+        assert_eq!(failures.banned_at(ip(1), start + 11 * MINUTE), None);
+    }
+
+    #[test]
+    fn ban_expires() {
+        let failures = failures();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            failures.failure_at(ip(1), start);
+        }
+        assert!(failures.banned_at(ip(1), start + 4 * MINUTE).is_some());
+        assert_eq!(failures.banned_at(ip(1), start + 5 * MINUTE), None);
+    }
+
+    #[test]
+    fn bans_again_after_ban_ended() {
+        let failures = failures();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            failures.failure_at(ip(1), start);
+        }
+        // the ban ended, but the window of the first failure is still open
+        let after_ban = start + 6 * MINUTE;
+        assert_eq!(failures.banned_at(ip(1), after_ban), None);
+
+        failures.failure_at(ip(1), after_ban);
+        assert_eq!(failures.banned_at(ip(1), after_ban), Some(5 * MINUTE));
+    }
+
+    #[test]
+    fn valid_requests_in_between_are_banned_too() {
+        let failures = failures();
+        let start = Instant::now();
+
+        // a client with a valid low-scope key guessing other keys between its valid requests,
+        // valid requests don't touch the failures, only the window lets them decay
+        for minute in 0..3 {
+            let now = start + minute * 2 * MINUTE;
+            assert_eq!(failures.banned_at(ip(1), now), None);
+            failures.failure_at(ip(1), now);
+        }
+        assert_eq!(
+            failures.banned_at(ip(1), start + 4 * MINUTE),
+            Some(5 * MINUTE)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use super::Scope;
+
+const REQUESTS_METRIC: &str = "cross_seed_tools_requests_total";
+
+/// Authenticated requests per API key name, scope and whether the key was allowed to access the
+/// scope. Exposed in the Prometheus text format.
+#[derive(Default)]
+pub(crate) struct KeyMetrics {
+    requests: Mutex<BTreeMap<(String, &'static str, bool), u64>>,
+}
+
+/// Escape a label value of the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl KeyMetrics {
+    /// Count a request authenticated with the API key `name`.
+    pub(crate) fn request(&self, name: &str, scope: Scope, allowed: bool) {
+        if let Ok(mut requests) = self.requests.lock() {
+            *requests
+                .entry((name.to_string(), scope.as_str(), allowed))
+                .or_default() += 1;
+        }
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut text = format!(
+            "# HELP {REQUESTS_METRIC} Authenticated requests by API key and scope.\n\
+             # TYPE {REQUESTS_METRIC} counter\n"
+        );
+
+        let Ok(requests) = self.requests.lock() else {
+            return text;
+        };
+
+        for ((name, scope, allowed), count) in requests.iter() {
+            let _ = writeln!(
+                text,
+                "{REQUESTS_METRIC}{{key=\"{}\",scope=\"{scope}\",result=\"{}\"}} {count}",
+                escape_label(name),
+                if *allowed { "allowed" } else { "forbidden" },
+            );
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_key_and_scope() {
+        let metrics = KeyMetrics::default();
+        metrics.request("sonarr", Scope::Xseed, true);
+        metrics.request("sonarr", Scope::Xseed, true);
+        metrics.request("sonarr", Scope::Announce, false);
+        metrics.request("autobrr", Scope::Announce, true);
+
+        assert_eq!(
+            metrics.render(),
+            "# HELP cross_seed_tools_requests_total Authenticated requests by API key and scope.\n\
+             # TYPE cross_seed_tools_requests_total counter\n\
+             cross_seed_tools_requests_total{key=\"autobrr\",scope=\"announce\",result=\"allowed\"} 1\n\
+             cross_seed_tools_requests_total{key=\"sonarr\",scope=\"announce\",result=\"forbidden\"} 1\n\
+             cross_seed_tools_requests_total{key=\"sonarr\",scope=\"xseed\",result=\"allowed\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn escapes_key_names() {
+        let metrics = KeyMetrics::default();
+        metrics.request("cert:a\"b\\c", Scope::Status, true);
+
+        assert!(metrics
+            .render()
+            .contains("key=\"cert:a\\\"b\\\\c\",scope=\"status\""));
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+
+use tracing::{info, warn, Span};
+
+use anyhow::{bail, Context};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use subtle::ConstantTimeEq;
+
+use axum::{
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+
+use crate::dashboard;
+use crate::db::now;
+use crate::tls::ClientCertificate;
+use crate::AppState;
+
+mod allowlist;
+mod bans;
+mod metrics;
+
+pub(crate) use allowlist::{allowlist_middleware, AllowList, ClientIp};
+pub(crate) use bans::{env_duration, AuthFailures, BanConfig, ClientStatus};
+pub(crate) use metrics::KeyMetrics;
+
+/// Group of routes an API key can be allowed to access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Scope {
+    Announce,
+    Xseed,
+    Inject,
+    History,
+    Dashboard,
+    Status,
+}
+
+const ALL_SCOPES: &[Scope] = &[
+    Scope::Announce,
+    Scope::Xseed,
+    Scope::Inject,
+    Scope::History,
+    Scope::Dashboard,
+    Scope::Status,
+];
+
+impl Scope {
+    fn parse(scope: &str) -> anyhow::Result<Vec<Scope>> {
+        let scopes = match scope.to_lowercase().as_str() {
+            "all" | "*" => ALL_SCOPES.to_vec(),
+            "announce" => vec![Scope::Announce],
+            "xseed" => vec![Scope::Xseed],
+            "inject" => vec![Scope::Inject],
+            "history" => vec![Scope::History],
+            "dashboard" => vec![Scope::Dashboard],
+            "status" => vec![Scope::Status],
+            _ => bail!("Unknown scope {scope}"),
+        };
+
+        Ok(scopes)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Announce => "announce",
+            Scope::Xseed => "xseed",
+            Scope::Inject => "inject",
+            Scope::History => "history",
+            Scope::Dashboard => "dashboard",
+            Scope::Status => "status",
+        }
+    }
+
+    /// Parse a comma-separated list of scopes.
+    fn parse_list(scopes: &str) -> anyhow::Result<Vec<Scope>> {
+        Ok(scopes
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Scope::parse)
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .concat())
+    }
+
+    /// Scope required to access `path`.
+    fn of_path(path: &str) -> Option<Scope> {
+        if path == "/announce" {
+            Some(Scope::Announce)
+        } else if path.starts_with("/xseed-") {
+            Some(Scope::Xseed)
+        } else if path.starts_with("/inject-seedbox-torrents-") {
+            Some(Scope::Inject)
+        } else if path.starts_with("/history") {
+            Some(Scope::History)
+        } else if path.starts_with("/dashboard") {
+            Some(Scope::Dashboard)
+        } else if path.starts_with("/status") {
+            Some(Scope::Status)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ApiKey {
+    pub name: String,
+    key: String,
+    scopes: Vec<Scope>,
+}
+
+impl ApiKey {
+    fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Compare in constant time to not leak the key through response timings.
+    fn matches(&self, key: &str) -> bool {
+        self.key.as_bytes().ct_eq(key.as_bytes()).into()
+    }
+}
+
+/// Ways an API key can be passed, each can be enabled separately.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AuthMethods {
+    /// `X-Api-Key` header
+    pub header: bool,
+    /// `?apikey=` query parameter
+    pub query: bool,
+    /// HTTP Basic auth with the API key as password
+    pub basic: bool,
+}
+
+pub(crate) fn env_flag(name: &str, default: bool) -> anyhow::Result<bool> {
+    match env::var(name) {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(true),
+            "false" | "0" | "no" => Ok(false),
+            _ => bail!("{name} must be true or false"),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+impl AuthMethods {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        Ok(AuthMethods {
+            header: env_flag("AUTH_HEADER_ENABLED", true)?,
+            query: env_flag("AUTH_QUERY_ENABLED", false)?,
+            basic: env_flag("AUTH_BASIC_ENABLED", false)?,
+        })
+    }
+}
+
+const QUERY_PARAMETER: &str = "apikey";
+
+/// Percent-decoded value of the API key query parameter, keys may contain `+ / = %`.
+fn key_from_query(uri: &Uri) -> Option<String> {
+    let Query(mut query) = Query::<HashMap<String, String>>::try_from_uri(uri).ok()?;
+
+    query.remove(QUERY_PARAMETER)
+}
+
+fn key_from_basic_auth(request: &Request) -> Option<String> {
+    let value = request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    // the user name is ignored, only the password has to match
+    let (_, password) = credentials.split_once(':')?;
+
+    Some(password.to_string())
+}
+
+/// `uri` with the value of the API key query parameter replaced, safe to be logged.
+pub(crate) fn redact_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((QUERY_PARAMETER, _)) => format!("{QUERY_PARAMETER}=REDACTED"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    format!("{}?{query}", uri.path())
+}
+
+/// Copy of `headers` with all credentials replaced, safe to be logged.
+pub(crate) fn redact_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in ["x-api-key", "authorization", "cookie"] {
+        if headers.contains_key(name) {
+            headers.insert(name, HeaderValue::from_static("REDACTED"));
+        }
+    }
+
+    headers
+}
+
+/// IP of the connected peer, if the server was started with connect info.
+fn peer_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// IP of the client, resolved through trusted proxies by the allowlist middleware.
+pub(crate) fn client_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| *ip)
+        .or_else(|| peer_ip(request))
+}
+
+/// Name of the API key used to authenticate the current request, available to handlers as an
+/// `Extension`.
+#[derive(Clone, Debug)]
+pub(crate) struct KeyName(pub String);
+
+/// Read the API keys from the environment. `API_KEY` is a key named `default` allowed to access
+/// everything. `API_KEYS` is a list of additional key names, each read from `API_KEY_<NAME>` and
+/// optionally restricted by `API_KEY_<NAME>_SCOPES`.
+pub(crate) fn api_keys_from_env() -> anyhow::Result<Vec<ApiKey>> {
+    let mut api_keys = Vec::new();
+
+    if let Ok(key) = env::var("API_KEY") {
+        api_keys.push(ApiKey {
+            name: "default".to_string(),
+            key,
+            scopes: ALL_SCOPES.to_vec(),
+        });
+    }
+
+    if let Ok(names) = env::var("API_KEYS") {
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let var = format!("API_KEY_{}", name.to_uppercase().replace('-', "_"));
+            let key = env::var(&var).with_context(|| format!("{var} is not set"))?;
+
+            let scopes = match env::var(format!("{var}_SCOPES")) {
+                Ok(scopes) => Scope::parse_list(&scopes)?,
+                Err(_) => ALL_SCOPES.to_vec(),
+            };
+
+            api_keys.push(ApiKey {
+                name: name.to_string(),
+                key,
+                scopes,
+            });
+        }
+    }
+
+    if api_keys.is_empty() {
+        bail!("Neither API_KEY nor API_KEYS is set");
+    }
+
+    for api_key in &api_keys {
+        info!("API key {} allows {:?}", api_key.name, api_key.scopes);
+    }
+
+    Ok(api_keys)
+}
+
+/// Scopes of clients authenticated with a verified TLS client certificate, read from
+/// `TLS_CLIENT_CERT_SCOPES`. Defaults to all scopes.
+pub(crate) fn client_cert_scopes_from_env() -> anyhow::Result<Vec<Scope>> {
+    match env::var("TLS_CLIENT_CERT_SCOPES") {
+        Ok(scopes) => Scope::parse_list(&scopes),
+        Err(_) => Ok(ALL_SCOPES.to_vec()),
+    }
+}
+
+/// Find the API key matching `key`, if it is allowed to access the dashboard.
+pub(crate) fn dashboard_key(api_keys: &[ApiKey], key: &str) -> Option<String> {
+    api_keys
+        .iter()
+        .find(|api_key| api_key.matches(key) && api_key.allows(Scope::Dashboard))
+        .map(|api_key| api_key.name.clone())
+}
+
+// Middleware for authentication
+pub(crate) async fn auth_middleware(
+    State(state): State<Arc<RwLock<AppState>>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let Some(scope) = Scope::of_path(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let ip = client_ip(&request);
+    let (auth_failures, key_metrics) = {
+        let read_guard = state.read().unwrap();
+        (
+            read_guard.auth_failures.clone(),
+            read_guard.key_metrics.clone(),
+        )
+    };
+
+    if let Some(remaining) = ip.and_then(|ip| auth_failures.banned(ip)) {
+        warn!("Rejected request to {path} from banned client {ip:?}");
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, remaining.as_secs().to_string())],
+        )
+            .into_response();
+    }
+
+    let (supplied, key) = {
+        let read_guard = state.read().unwrap();
+        let methods = read_guard.auth_methods;
+
+        let header = methods
+            .header
+            .then(|| request.headers().get("X-Api-Key"))
+            .flatten()
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let query = methods
+            .query
+            .then(|| key_from_query(request.uri()))
+            .flatten();
+        let basic = methods
+            .basic
+            .then(|| key_from_basic_auth(&request))
+            .flatten();
+        let supplied = header.or(query).or(basic);
+        let client_cert = request
+            .extensions()
+            .get::<Option<ClientCertificate>>()
+            .cloned()
+            .flatten();
+
+        let key = match (&supplied, client_cert) {
+            (Some(api_key), _) => read_guard
+                .api_keys
+                .iter()
+                .find(|k| k.matches(api_key))
+                .map(|k| (k.name.clone(), k.allows(scope))),
+            // the certificate was already verified during the TLS handshake
+            (None, Some(ClientCertificate(name))) => Some((
+                format!("cert:{name}"),
+                read_guard.client_cert_scopes.contains(&scope),
+            )),
+            // dashboard sessions are only valid for the dashboard itself
+            (None, None) => dashboard::session_from_headers(request.headers())
+                .and_then(|session| read_guard.dashboard_sessions.key_name(&session, now()))
+                .map(|name| (name.to_string(), scope == Scope::Dashboard)),
+        };
+
+        (supplied.is_some(), key)
+    };
+
+    if let Some((name, allowed)) = &key {
+        key_metrics.request(name, scope, *allowed);
+    }
+
+    match key {
+        Some((name, true)) => {
+            // failures only decay with AUTH_FAILURE_WINDOW, a valid low-scope key must not
+            // reset them while guessing others
+            Span::current().record("api_key", &name);
+            request.extensions_mut().insert(KeyName(name));
+            next.run(request).await
+        }
+        Some((name, false)) => {
+            warn!("API key {name} is not allowed to access {path}");
+            StatusCode::FORBIDDEN.into_response()
+        }
+        None if scope == Scope::Dashboard && !supplied => {
+            Redirect::to("/dashboard/login").into_response()
+        }
+        None => {
+            warn!(
+                "UNAUTHORIZED request from {ip:?}: {} {} {:?}",
+                request.method(),
+                redact_uri(request.uri()),
+                redact_headers(request.headers())
+            );
+            if let Some(ip) = ip {
+                auth_failures.failure(ip);
+            }
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+/// Clients with failed authentications and their bans.
+pub(crate) async fn auth_status(
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Json<Vec<ClientStatus>> {
+    let auth_failures = state.read().unwrap().auth_failures.clone();
+
+    Json(auth_failures.status())
+}
+
+/// Metrics in the Prometheus text format.
+pub(crate) async fn auth_metrics(State(state): State<Arc<RwLock<AppState>>>) -> Response {
+    let key_metrics = state.read().unwrap().key_metrics.clone();
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        key_metrics.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{body::Body, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    fn api_key(key: &str) -> ApiKey {
+        ApiKey {
+            name: "sonarr".to_string(),
+            key: key.to_string(),
+            scopes: vec![Scope::Xseed, Scope::Inject],
+        }
+    }
+
+    #[test]
+    fn api_key_matches() {
+        let api_key = api_key("s3cr3t-key");
+
+        assert!(api_key.matches("s3cr3t-key"));
+        assert!(!api_key.matches("s3cr3t-kez"));
+        assert!(!api_key.matches("s3cr3t-ke"));
+        assert!(!api_key.matches("s3cr3t-key "));
+        assert!(!api_key.matches("S3CR3T-KEY"));
+        assert!(!api_key.matches(""));
+    }
+
+    #[test]
+    fn api_key_scopes() {
+        let api_key = api_key("key");
+
+        assert!(api_key.allows(Scope::Xseed));
+        assert!(!api_key.allows(Scope::Dashboard));
+        assert_eq!(Scope::of_path("/xseed-sonarr/4k"), Some(Scope::Xseed));
+        assert_eq!(Scope::of_path("/unknown"), None);
+    }
+
+    async fn status(router: &Router, path: &str, key: &str) -> StatusCode {
+        let mut request = Request::post(path)
+            .header("X-Api-Key", key)
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ClientIp(IpAddr::from([192, 168, 1, 10])));
+
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn valid_key_does_not_reset_failures() {
+        env::set_var("HISTORY_DB_PATH", ":memory:");
+        env::set_var("API_KEY", "unused");
+        let state = crate::create_config_state().unwrap();
+        // allows xseed and inject
+        state.write().unwrap().api_keys = vec![api_key("low-scope")];
+
+        let router = Router::new()
+            .route("/xseed-sonarr", post(|| async { StatusCode::OK }))
+            .route("/announce", post(|| async { StatusCode::OK }))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ))
+            .with_state(state);
+
+        // guessing other keys between valid requests, AUTH_MAX_FAILURES defaults to 5
+        for _ in 0..5 {
+            assert_eq!(
+                status(&router, "/xseed-sonarr", "low-scope").await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status(&router, "/announce", "guess").await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(
+            status(&router, "/xseed-sonarr", "low-scope").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    fn query_key(uri: &str) -> Option<String> {
+        key_from_query(&uri.parse().unwrap())
+    }
+
+    #[test]
+    fn decodes_query_key() {
+        assert_eq!(
+            query_key("/xseed?apikey=abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            query_key("/xseed?instance=4k&apikey=a%2Bb%2Fc%3D%3D%25"),
+            Some("a+b/c==%".to_string())
+        );
+        // an unencoded `+` is a space in a query string
+        assert_eq!(query_key("/xseed?apikey=a+b"), Some("a b".to_string()));
+        assert_eq!(query_key("/xseed?api=abc123"), None);
+        assert_eq!(query_key("/xseed"), None);
+
+        let api_key = api_key("k+y/with=sign%");
+        assert!(api_key.matches(&query_key("/xseed?apikey=k%2By%2Fwith%3Dsign%25").unwrap()));
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use tracing::{field::Empty, info, info_span, warn, Instrument};
+
+use anyhow::{anyhow, bail, Context};
+
+use clap::ValueEnum;
+
+use serde_json::Value;
+
+use tokio::task::JoinSet;
+
+use qbit_rs::{
+    model::{Credential, GetTorrentListArg},
+    Qbit,
+};
+
+use crate::arr::{arr_imports_since, HistoryRecord};
+use crate::data_types::radarr::{RadarrConnectWebhook, RadarrMovieFile, RadarrRelease};
+use crate::data_types::sonarr::{SonarrConnectWebhook, SonarrEpisodeFile, SonarrRelease};
+use crate::data_types::ArrImport;
+use crate::db::{EntryFields, HistoryFilter, Origin, Record, Status};
+use crate::history::db;
+use crate::inject_seedbox_torrents::run_inject;
+use crate::jobs::run_recorded_job;
+use crate::xseed::xseed;
+use crate::AppState;
+
+fn qbit(host: &str, user: String, password: String) -> anyhow::Result<Qbit> {
+    let host = reqwest::Url::parse(host)?;
+
+    Ok(Qbit::new(host, Credential::new(user, password)))
+}
+
+/// Torrent on the seedbox whose data was found locally.
+struct Candidate {
+    hash: String,
+    name: String,
+}
+
+/// Inject all torrents of a seedbox `category` into the local qbittorrent, which are not there
+/// yet but whose data already exists under `QBITTORRENT_LOCAL_DIR/<category>`. Without `apply`
+/// only a report of what would be injected is logged.
+pub(crate) async fn backfill_inject_command(
+    state: Arc<RwLock<AppState>>,
+    category: String,
+    apply: bool,
+    batch_size: usize,
+    pause: Duration,
+) -> anyhow::Result<()> {
+    let (qbit_seedbox, qbit_local, qbittorrent_local_dir) = {
+        let read_guard = state
+            .read()
+            .map_err(|_| anyhow!("Could not read from state."))?;
+
+        let qbit_seedbox = qbit(
+            read_guard
+                .qbittorrent_seedbox_host
+                .as_deref()
+                .context("QBITORRENT_SEEDBOX_HOST is not set.")?,
+            read_guard
+                .qbittorrent_seedbox_user
+                .clone()
+                .context("QBITORRENT_SEEDBOX_USER is not set.")?,
+            read_guard
+                .qbittorrent_seedbox_password
+                .clone()
+                .context("QBITORRENT_SEEDBOX_PASSWORD is not set.")?,
+        )?;
+        let qbit_local = qbit(
+            read_guard
+                .qbittorrent_local_host
+                .as_deref()
+                .context("QBITORRENT_LOCAL_HOST is not set.")?,
+            read_guard
+                .qbittorrent_local_user
+                .clone()
+                .context("QBITORRENT_LOCAL_USER is not set.")?,
+            read_guard
+                .qbittorrent_local_password
+                .clone()
+                .context("QBITORRENT_LOCAL_PASSWORD is not set.")?,
+        )?;
+        let qbittorrent_local_dir = read_guard
+            .qbittorrent_local_dir
+            .clone()
+            .context("QBITORRENT_LOCAL_DIR is not set.")?;
+
+        (qbit_seedbox, qbit_local, qbittorrent_local_dir)
+    };
+
+    let seedbox_torrents = qbit_seedbox
+        .get_torrent_list(GetTorrentListArg {
+            category: Some(category.clone()),
+            ..Default::default()
+        })
+        .await
+        .context("Could not list the seedbox torrents.")?;
+    let local_hashes = qbit_local
+        .get_torrent_list(GetTorrentListArg::default())
+        .await
+        .context("Could not list the local torrents.")?
+        .into_iter()
+        .filter_map(|torrent| torrent.hash)
+        .map(|hash| hash.to_lowercase())
+        .collect::<HashSet<_>>();
+
+    let local_dir = Path::new(&qbittorrent_local_dir).join(&category);
+
+    let mut candidates = Vec::new();
+    let (mut already_local, mut missing_data) = (0, 0);
+
+    for torrent in seedbox_torrents {
+        let (Some(hash), Some(name)) = (torrent.hash, torrent.name) else {
+            continue;
+        };
+
+        if local_hashes.contains(&hash.to_lowercase()) {
+            already_local += 1;
+            continue;
+        }
+
+        if !local_dir.join(&name).exists() {
+            info!("No local data for {name} ({hash}).");
+            missing_data += 1;
+            continue;
+        }
+
+        info!("Will inject {name} ({hash}).");
+        candidates.push(Candidate { hash, name });
+    }
+
+    info!(
+        "Category {category}: {} to inject, {already_local} already in the local client, \
+         {missing_data} without local data.",
+        candidates.len()
+    );
+
+    if !apply {
+        info!("Dry run, nothing was injected. Run again with --apply to inject.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    let batches = candidates.chunks(batch_size.max(1)).collect::<Vec<_>>();
+
+    for (i, batch) in batches.iter().enumerate() {
+        info!("Injecting batch {} of {}...", i + 1, batches.len());
+
+        let mut tasks = JoinSet::new();
+        for candidate in *batch {
+            let state = state.clone();
+            let hash = candidate.hash.clone();
+            let name = candidate.name.clone();
+            let category = category.clone();
+            tasks.spawn(async move {
+                let result = run_inject(state, hash, Some(category)).await;
+                (name, result)
+            });
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            match result? {
+                (name, Ok(())) => info!("Injected {name}."),
+                (name, Err(err)) => {
+                    warn!("Could not inject {name}: {err}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if i + 1 < batches.len() {
+            tokio::time::sleep(pause).await;
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{failed} of {} torrents could not be injected.",
+            candidates.len()
+        );
+    }
+
+    info!("Injected {} torrents.", candidates.len());
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Arr {
+    Sonarr,
+    Radarr,
+}
+
+/// Rebuild the webhooks Sonarr would have sent for `records`. Episodes imported from the same
+/// download are combined, multiple files make it a season pack.
+fn sonarr_webhooks(records: Vec<HistoryRecord>) -> Vec<SonarrConnectWebhook> {
+    let mut webhooks: Vec<SonarrConnectWebhook> = Vec::new();
+
+    for record in records {
+        let (Some(download_id), Some(path)) =
+            (record.download_id.clone(), record.data("importedPath"))
+        else {
+            continue;
+        };
+
+        if let Some(webhook) = webhooks
+            .iter_mut()
+            .find(|webhook| webhook.download_id.as_ref() == Some(&download_id))
+        {
+            let episode_files = webhook.episode_files.get_or_insert_with(Vec::new);
+            // files containing multiple episodes show up once per episode
+            if !episode_files
+                .iter()
+                .any(|file| file.path.as_ref() == Some(&path))
+            {
+                episode_files.push(SonarrEpisodeFile {
+                    path: Some(path),
+                    ..Default::default()
+                });
+                if let Some(release) = &mut webhook.release {
+                    release.release_type = Some("SeasonPack".to_string());
+                }
+            }
+            continue;
+        }
+
+        webhooks.push(SonarrConnectWebhook {
+            destination_path: Path::new(&path)
+                .parent()
+                .map(|parent| parent.to_string_lossy().into_owned()),
+            instance_name: None,
+            download_client: record
+                .data("downloadClientName")
+                .or_else(|| record.data("downloadClient")),
+            download_id: Some(download_id),
+            event_type: "Download".to_string(),
+            episode_files: Some(vec![SonarrEpisodeFile {
+                path: Some(path),
+                ..Default::default()
+            }]),
+            release: Some(SonarrRelease {
+                indexer: record.data("indexer"),
+                release_title: Some(record.source_title.clone()),
+                release_type: Some("SingleEpisode".to_string()),
+                ..Default::default()
+            }),
+            source_path: record.data("droppedPath"),
+            ..Default::default()
+        });
+    }
+
+    webhooks
+}
+
+/// Rebuild the webhooks Radarr would have sent for `records`.
+fn radarr_webhooks(records: Vec<HistoryRecord>) -> Vec<RadarrConnectWebhook> {
+    records
+        .into_iter()
+        .filter_map(|record| {
+            let path = record.data("importedPath")?;
+            let source_path = record.data("droppedPath");
+
+            Some(RadarrConnectWebhook {
+                download_client: record
+                    .data("downloadClientName")
+                    .or_else(|| record.data("downloadClient")),
+                download_id: Some(record.download_id.clone()?),
+                event_type: "Download".to_string(),
+                movie_file: Some(RadarrMovieFile {
+                    path: Some(path),
+                    source_path: source_path.clone(),
+                    ..Default::default()
+                }),
+                source_path,
+                release: Some(RadarrRelease {
+                    release_title: Some(record.source_title.clone()),
+                    indexer: record.data("indexer"),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// `records` imported until `until`. Records without a readable date are left out with a warning,
+/// they can't be placed in the time range.
+fn imported_until(records: Vec<HistoryRecord>, until: SystemTime) -> Vec<HistoryRecord> {
+    records
+        .into_iter()
+        .filter(|record| match humantime::parse_rfc3339_weak(&record.date) {
+            Ok(date) => date <= until,
+            Err(err) => {
+                warn!(
+                    "Skipping {}, its date {} is invalid: {err}",
+                    record.source_title, record.date
+                );
+                false
+            }
+        })
+        .collect()
+}
+
+/// Reconstructed import, ready to be passed to `xseed`.
+struct Import {
+    endpoint: &'static str,
+    fields: EntryFields,
+    payload: Value,
+    request: ArrImport,
+}
+
+async fn arr_imports(
+    state: &Arc<RwLock<AppState>>,
+    arr: Arr,
+    since: SystemTime,
+    until: SystemTime,
+) -> anyhow::Result<Vec<Import>> {
+    let (arr_url, arr_api_key) = {
+        let read_guard = state
+            .read()
+            .map_err(|_| anyhow!("Could not read from state."))?;
+
+        match arr {
+            Arr::Sonarr => (
+                read_guard
+                    .sonarr_url
+                    .clone()
+                    .context("SONARR_URL is not set.")?,
+                read_guard
+                    .sonarr_api_key
+                    .clone()
+                    .context("SONARR_API_KEY is not set.")?,
+            ),
+            Arr::Radarr => (
+                read_guard
+                    .radarr_url
+                    .clone()
+                    .context("RADARR_URL is not set.")?,
+                read_guard
+                    .radarr_api_key
+                    .clone()
+                    .context("RADARR_API_KEY is not set.")?,
+            ),
+        }
+    };
+
+    let records = arr_imports_since(&arr_url, &arr_api_key, since)
+        .await
+        .with_context(|| format!("Could not read the {arr:?} history."))?;
+    let records = imported_until(records, until);
+
+    let imports = match arr {
+        Arr::Sonarr => sonarr_webhooks(records)
+            .into_iter()
+            .map(|webhook| {
+                let request = ArrImport::from(&webhook);
+                Ok(Import {
+                    endpoint: "/xseed-sonarr",
+                    fields: EntryFields::from(&request),
+                    payload: serde_json::to_value(&webhook)?,
+                    request,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        Arr::Radarr => radarr_webhooks(records)
+            .into_iter()
+            .map(|webhook| {
+                let request = ArrImport::from(&webhook);
+                Ok(Import {
+                    endpoint: "/xseed-radarr",
+                    fields: EntryFields::from(&request),
+                    payload: serde_json::to_value(&webhook)?,
+                    request,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+
+    Ok(imports)
+}
+
+/// Whether the history already contains a successful xseed of the download.
+fn already_xseeded(state: &Arc<RwLock<AppState>>, fields: &EntryFields) -> anyhow::Result<bool> {
+    let filter = HistoryFilter {
+        endpoint: Some("/xseed-sonarr,/xseed-radarr".to_string()),
+        download_id: fields.download_id.clone(),
+        status: Some(Status::Success),
+        ..Default::default()
+    };
+
+    Ok(db(state)?
+        .list(&filter)?
+        .iter()
+        .any(|entry| !entry.dry_run && entry.download_client == fields.download_client))
+}
+
+/// Run the xseed logic for all downloads imported into Sonarr / Radarr between `since` and
+/// `until` ago, e.g. while cross-seed-tools was down. Downloads which were already xseeded
+/// successfully are skipped. Each run is stored in the history like a webhook request.
+pub(crate) async fn backfill_xseed_command(
+    state: Arc<RwLock<AppState>>,
+    arr: Option<Arr>,
+    since: Duration,
+    until: Option<Duration>,
+    pace: Duration,
+) -> anyhow::Result<()> {
+    let now = SystemTime::now();
+    let since = now - since;
+    let until = now - until.unwrap_or_default();
+
+    let arrs = match arr {
+        Some(arr) => vec![arr],
+        None => {
+            let read_guard = state
+                .read()
+                .map_err(|_| anyhow!("Could not read from state."))?;
+
+            [
+                (Arr::Sonarr, read_guard.sonarr_url.is_some()),
+                (Arr::Radarr, read_guard.radarr_url.is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(arr, configured)| configured.then_some(arr))
+            .collect()
+        }
+    };
+    if arrs.is_empty() {
+        bail!("Neither SONARR_URL nor RADARR_URL is set.");
+    }
+
+    let mut imports = Vec::new();
+    for arr in arrs {
+        let arr_imports = arr_imports(&state, arr, since, until).await?;
+        info!(
+            "Found {} imports in the {arr:?} history.",
+            arr_imports.len()
+        );
+        imports.extend(arr_imports);
+    }
+
+    let (mut skipped, mut failed) = (0, 0);
+    let total = imports.len();
+
+    for (i, import) in imports.into_iter().enumerate() {
+        let title = import.fields.release_title.clone().unwrap_or_default();
+
+        if already_xseeded(&state, &import.fields)? {
+            info!("{title} was already xseeded.");
+            skipped += 1;
+            continue;
+        }
+
+        let record = db(&state)?.start(
+            import.endpoint,
+            &Origin::command_line(&state),
+            import.fields,
+            &import.payload,
+        )?;
+        let span = info_span!(
+            "backfill",
+            release_title = Empty,
+            download_id = Empty,
+            client = Empty,
+        );
+        let job = {
+            let state = state.clone();
+            let request = import.request;
+            |record: Record| async move { xseed(request, state, &record).await }.instrument(span)
+        };
+
+        match run_recorded_job(&state, record, job).await {
+            Ok(()) => info!("Xseeded {title} ({}/{total}).", i + 1),
+            Err(err) => {
+                warn!("Could not xseed {title}: {err}");
+                failed += 1;
+            }
+        }
+
+        if i + 1 < total {
+            tokio::time::sleep(pace).await;
+        }
+    }
+
+    info!("Backfill finished: {total} imports, {skipped} already xseeded, {failed} failed.");
+
+    if failed > 0 {
+        bail!("{failed} of {total} imports could not be xseeded.");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn record(title: &str, download_id: Option<&str>, date: &str, data: Value) -> HistoryRecord {
+        serde_json::from_value(json!({
+            "sourceTitle": title,
+            "downloadId": download_id,
+            "date": date,
+            "data": data,
+        }))
+        .unwrap()
+    }
+
+    fn episode(download_id: &str, path: &str) -> HistoryRecord {
+        record(
+            "The.Bear.S01.1080p.WEB.h264-GROUP",
+            Some(download_id),
+            "2024-06-01T12:00:00Z",
+            json!({
+                "importedPath": path,
+                "droppedPath": "/downloads/The.Bear.S01.1080p.WEB.h264-GROUP",
+                "downloadClientName": "qbittorrent",
+                "indexer": "Tracker",
+            }),
+        )
+    }
+
+    fn paths(webhook: &SonarrConnectWebhook) -> Vec<&str> {
+        webhook
+            .episode_files
+            .iter()
+            .flatten()
+            .filter_map(|file| file.path.as_deref())
+            .collect()
+    }
+
+    fn release_type(webhook: &SonarrConnectWebhook) -> Option<&str> {
+        webhook.release.as_ref()?.release_type.as_deref()
+    }
+
+    #[test]
+    fn sonarr_single_episode() {
+        let webhooks = sonarr_webhooks(vec![episode("HASH1", "/tv/The Bear/Season 1/S01E01.mkv")]);
+
+        assert_eq!(webhooks.len(), 1);
+        let webhook = &webhooks[0];
+        assert_eq!(webhook.event_type, "Download");
+        assert_eq!(webhook.download_id.as_deref(), Some("HASH1"));
+        assert_eq!(webhook.download_client.as_deref(), Some("qbittorrent"));
+        assert_eq!(
+            webhook.destination_path.as_deref(),
+            Some("/tv/The Bear/Season 1")
+        );
+        assert_eq!(paths(webhook), ["/tv/The Bear/Season 1/S01E01.mkv"]);
+        assert_eq!(release_type(webhook), Some("SingleEpisode"));
+
+        let release = webhook.release.as_ref().unwrap();
+        assert_eq!(
+            release.release_title.as_deref(),
+            Some("The.Bear.S01.1080p.WEB.h264-GROUP")
+        );
+        assert_eq!(release.indexer.as_deref(), Some("Tracker"));
+    }
+
+    #[test]
+    fn sonarr_merges_season_pack() {
+        let webhooks = sonarr_webhooks(vec![
+            episode("HASH1", "/tv/The Bear/Season 1/S01E01.mkv"),
+            episode("HASH2", "/tv/Other/Season 1/S01E01.mkv"),
+            episode("HASH1", "/tv/The Bear/Season 1/S01E02.mkv"),
+        ]);
+
+        assert_eq!(webhooks.len(), 2);
+        assert_eq!(
+            paths(&webhooks[0]),
+            [
+                "/tv/The Bear/Season 1/S01E01.mkv",
+                "/tv/The Bear/Season 1/S01E02.mkv"
+            ]
+        );
+        assert_eq!(release_type(&webhooks[0]), Some("SeasonPack"));
+        assert_eq!(release_type(&webhooks[1]), Some("SingleEpisode"));
+    }
+
+    #[test]
+    fn sonarr_multi_episode_file_is_not_a_season_pack() {
+        // a file containing S01E01 and S01E02 is recorded once per episode
+        let webhooks = sonarr_webhooks(vec![
+            episode("HASH1", "/tv/The Bear/Season 1/S01E01-E02.mkv"),
+            episode("HASH1", "/tv/The Bear/Season 1/S01E01-E02.mkv"),
+        ]);
+
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(
+            paths(&webhooks[0]),
+            ["/tv/The Bear/Season 1/S01E01-E02.mkv"]
+        );
+        assert_eq!(release_type(&webhooks[0]), Some("SingleEpisode"));
+    }
+
+    #[test]
+    fn sonarr_skips_incomplete_records() {
+        let webhooks = sonarr_webhooks(vec![
+            record(
+                "No.Download.Id",
+                None,
+                "2024-06-01T12:00:00Z",
+                json!({"importedPath": "/tv/a.mkv"}),
+            ),
+            record(
+                "No.Path",
+                Some("HASH1"),
+                "2024-06-01T12:00:00Z",
+                json!({"importedPath": ""}),
+            ),
+        ]);
+
+        assert!(webhooks.is_empty());
+    }
+
+    #[test]
+    fn radarr_webhooks_per_record() {
+        let webhooks = radarr_webhooks(vec![
+            record(
+                "Arrival.2016.1080p.BluRay.DTS.x264-DON",
+                Some("HASH1"),
+                "2024-06-01T12:00:00Z",
+                json!({
+                    "importedPath": "/movies/Arrival (2016)/Arrival.mkv",
+                    "droppedPath": "/downloads/Arrival.2016.1080p.BluRay.DTS.x264-DON/Arrival.mkv",
+                    "downloadClient": "qbittorrent",
+                }),
+            ),
+            record("No.Path", Some("HASH2"), "2024-06-01T12:00:00Z", json!({})),
+            record(
+                "No.Download.Id",
+                None,
+                "2024-06-01T12:00:00Z",
+                json!({"importedPath": "/movies/Other/Other.mkv"}),
+            ),
+        ]);
+
+        assert_eq!(webhooks.len(), 1);
+        let webhook = &webhooks[0];
+        assert_eq!(webhook.download_id.as_deref(), Some("HASH1"));
+        assert_eq!(webhook.download_client.as_deref(), Some("qbittorrent"));
+        let movie_file = webhook.movie_file.as_ref().unwrap();
+        assert_eq!(
+            movie_file.path.as_deref(),
+            Some("/movies/Arrival (2016)/Arrival.mkv")
+        );
+        assert_eq!(movie_file.source_path, webhook.source_path);
+        assert_eq!(
+            webhook.release.as_ref().unwrap().release_title.as_deref(),
+            Some("Arrival.2016.1080p.BluRay.DTS.x264-DON")
+        );
+    }
+
+    #[test]
+    fn filters_by_date() {
+        let until = humantime::parse_rfc3339("2024-06-02T00:00:00Z").unwrap();
+        let records = imported_until(
+            vec![
+                record("Before", Some("1"), "2024-06-01T12:00:00Z", json!({})),
+                record(
+                    "Fraction",
+                    Some("2"),
+                    "2024-06-01T12:00:00.1234567Z",
+                    json!({}),
+                ),
+                record("After", Some("3"), "2024-06-03T12:00:00Z", json!({})),
+                record("Invalid", Some("4"), "yesterday", json!({})),
+            ],
+            until,
+        );
+
+        let titles = records
+            .iter()
+            .map(|record| record.source_title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["Before", "Fraction"]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 use reqwest::Client;
@@ -55,3 +57,14 @@ pub(crate) async fn cross_seed_webhook(
 
     Ok(response.status())
 }
+
+pub(crate) async fn cross_seed_ping(cross_seed_url: &str) -> anyhow::Result<StatusCode> {
+    let client = Client::new();
+    let response = client
+        .get(format!("{cross_seed_url}/api/ping"))
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await?;
+
+    Ok(response.status())
+}
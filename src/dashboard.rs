@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, UNIX_EPOCH};
+
+use tracing::{error, info, warn};
+
+use serde::Deserialize;
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+
+use uuid::Uuid;
+
+use crate::auth::{dashboard_key, env_duration, ClientIp};
+use crate::db::{now, HistoryEntry, HistoryFilter, Origin, Status};
+use crate::health::{integration_health, Health};
+use crate::history::{db, replay, REPLAYABLE_ENDPOINTS};
+use crate::jobs::{cancel_job, running_jobs};
+use crate::tls::TlsFiles;
+use crate::AppState;
+
+pub(crate) const SESSION_COOKIE: &str = "cross_seed_tools_session";
+
+#[derive(Debug, Clone)]
+struct Session {
+    /// Name of the API key used to log in
+    key_name: String,
+    /// Unix timestamp (seconds) the session ends
+    expires: i64,
+}
+
+/// Logged in dashboard sessions by session id. Sessions end after `DASHBOARD_SESSION_LIFETIME`,
+/// the cookie expires at the same time.
+#[derive(Debug, Clone)]
+pub(crate) struct Sessions {
+    lifetime: Duration,
+    /// Only send the cookie over HTTPS
+    secure: bool,
+    sessions: HashMap<String, Session>,
+}
+
+impl Sessions {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        Ok(Sessions {
+            lifetime: env_duration(
+                "DASHBOARD_SESSION_LIFETIME",
+                Duration::from_secs(12 * 60 * 60),
+            )?,
+            secure: TlsFiles::from_env()?.is_some(),
+            sessions: HashMap::new(),
+        })
+    }
+
+    /// Start a session for the API key `key_name`, returns the session id. Ended sessions are
+    /// dropped on the way.
+    fn create(&mut self, key_name: &str, now: i64) -> String {
+        self.sessions.retain(|_, session| session.expires > now);
+
+        let id = Uuid::new_v4().to_string();
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                key_name: key_name.to_string(),
+                expires: now + self.lifetime.as_secs() as i64,
+            },
+        );
+
+        id
+    }
+
+    /// Name of the API key the session `id` was started with, if it didn't end yet.
+    pub(crate) fn key_name(&self, id: &str, now: i64) -> Option<&str> {
+        self.sessions
+            .get(id)
+            .filter(|session| session.expires > now)
+            .map(|session| session.key_name.as_str())
+    }
+
+    /// `Set-Cookie` value for the session `id`.
+    fn cookie(&self, id: &str) -> String {
+        let mut cookie = format!(
+            "{SESSION_COOKIE}={id}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+            self.lifetime.as_secs()
+        );
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        cookie
+    }
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; width: 100%; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+.success { color: #2e7d32; } .failed { color: #c62828; } .running { color: #f9a825; }
+.cancelled { color: #757575; }
+form { display: inline; }
+";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn format_time(timestamp: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64);
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+fn status_class(status: Status) -> &'static str {
+    match status {
+        Status::Running => "running",
+        Status::Success => "success",
+        Status::Failed => "failed",
+        Status::Cancelled => "cancelled",
+    }
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title>\
+         <style>{STYLE}</style></head><body>{body}</body></html>"
+    ))
+}
+
+/// Session id from the `Cookie` header, if any.
+pub(crate) fn session_from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+fn render_health(html: &mut String, health: &[Health]) {
+    html.push_str(
+        "<h2>Integrations</h2><table><tr><th>Name</th><th>Status</th><th>Detail</th></tr>",
+    );
+    for health in health {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td>{}</td></tr>",
+            health.name,
+            if health.ok { "success" } else { "failed" },
+            if health.ok { "ok" } else { "error" },
+            escape(&health.detail),
+        );
+    }
+    html.push_str("</table>");
+}
+
+fn render_running(html: &mut String, entries: &[HistoryEntry], cancellable: &[i64]) {
+    html.push_str(
+        "<h2>Pending jobs</h2><table><tr><th>Id</th><th>Received</th><th>Endpoint</th>\
+         <th>Release</th><th></th></tr>",
+    );
+    for entry in entries {
+        let cancel = if cancellable.contains(&entry.id) {
+            format!(
+                "<form method=\"post\" action=\"/dashboard/jobs/{}/cancel\">\
+                 <button>Cancel</button></form>",
+                entry.id
+            )
+        } else {
+            String::new()
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{cancel}</td></tr>",
+            entry.id,
+            format_time(entry.received_at),
+            escape(&entry.endpoint),
+            escape(entry.release_title.as_deref().unwrap_or_default()),
+        );
+    }
+    html.push_str("</table>");
+}
+
+fn render_announces(html: &mut String, state: &Arc<RwLock<AppState>>, entries: &[HistoryEntry]) {
+    html.push_str(
+        "<h2>Recent announces</h2><table><tr><th>Id</th><th>Received</th><th>Release</th>\
+         <th>Status</th><th>Targets</th></tr>",
+    );
+    let db = db(state).ok();
+    for entry in entries {
+        let calls = db
+            .as_ref()
+            .and_then(|db| db.calls(entry.id).ok())
+            .unwrap_or_default();
+        let targets = calls
+            .iter()
+            .map(|call| {
+                format!(
+                    "<span class=\"{}\">{}: {}</span>",
+                    if call.success { "success" } else { "failed" },
+                    escape(&call.target),
+                    escape(call.detail.as_deref().unwrap_or_default()),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("<br>");
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"{}\">{:?}</td><td>{targets}</td></tr>",
+            entry.id,
+            format_time(entry.received_at),
+            escape(entry.release_title.as_deref().unwrap_or_default()),
+            status_class(entry.status),
+            entry.status,
+        );
+    }
+    html.push_str("</table>");
+}
+
+fn render_jobs(html: &mut String, entries: &[HistoryEntry]) {
+    html.push_str(
+        "<h2>Recent xseed / inject jobs</h2><table><tr><th>Id</th><th>Received</th>\
+         <th>Endpoint</th><th>Release</th><th>Client</th><th>Status</th><th>Error</th>\
+         <th></th></tr>",
+    );
+    for entry in entries {
+        let replay = if entry.status != Status::Running {
+            format!(
+                "<form method=\"post\" action=\"/dashboard/history/{}/replay\">\
+                 <button>Replay</button></form>",
+                entry.id
+            )
+        } else {
+            String::new()
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td class=\"{}\">{:?}</td><td>{}</td><td>{replay}</td></tr>",
+            entry.id,
+            format_time(entry.received_at),
+            escape(&entry.endpoint),
+            escape(entry.release_title.as_deref().unwrap_or_default()),
+            escape(entry.download_client.as_deref().unwrap_or_default()),
+            status_class(entry.status),
+            entry.status,
+            escape(entry.error.as_deref().unwrap_or_default()),
+        );
+    }
+    html.push_str("</table>");
+}
+
+async fn render_dashboard(state: &Arc<RwLock<AppState>>) -> anyhow::Result<String> {
+    let db = db(state)?;
+
+    let running = db.list(&HistoryFilter {
+        status: Some(Status::Running),
+        ..Default::default()
+    })?;
+    let announces = db.list(&HistoryFilter {
+        endpoint: Some("/announce".to_string()),
+        limit: Some(25),
+        ..Default::default()
+    })?;
+    let jobs = db.list(&HistoryFilter {
+        endpoint: Some(REPLAYABLE_ENDPOINTS.join(",")),
+        limit: Some(25),
+        ..Default::default()
+    })?;
+    let cancellable = running_jobs(state)?;
+    let health = integration_health(state).await?;
+
+    let mut html = String::from("<h1>cross-seed-tools</h1>");
+    render_health(&mut html, &health);
+    render_running(&mut html, &running, &cancellable);
+    render_announces(&mut html, state, &announces);
+    render_jobs(&mut html, &jobs);
+
+    Ok(html)
+}
+
+pub(crate) async fn dashboard(State(state): State<Arc<RwLock<AppState>>>) -> Response {
+    match render_dashboard(&state).await {
+        Ok(body) => page("cross-seed-tools", &body).into_response(),
+        Err(err) => {
+            error!("Error occured: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Login {
+    api_key: String,
+}
+
+fn login_page(message: &str) -> Html<String> {
+    page(
+        "cross-seed-tools login",
+        &format!(
+            "<h1>cross-seed-tools</h1><p>{message}</p>\
+             <form method=\"post\" action=\"/dashboard/login\">\
+             <input type=\"password\" name=\"api_key\" placeholder=\"API key\" autofocus> \
+             <button>Login</button></form>"
+        ),
+    )
+}
+
+pub(crate) async fn dashboard_login_form() -> Html<String> {
+    login_page("")
+}
+
+pub(crate) async fn dashboard_login(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    Form(login): Form<Login>,
+) -> Response {
+    let Ok(mut write_guard) = state.write() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    if write_guard.auth_failures.banned(ip).is_some() {
+        warn!("Rejected dashboard login from banned client {ip}");
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    }
+
+    let Some(name) = dashboard_key(&write_guard.api_keys, &login.api_key) else {
+        warn!("Failed dashboard login from {ip}.");
+        write_guard.auth_failures.failure(ip);
+        return (StatusCode::UNAUTHORIZED, login_page("Invalid API key.")).into_response();
+    };
+
+    let sessions = &mut write_guard.dashboard_sessions;
+    let session = sessions.create(&name, now());
+    let cookie = sessions.cookie(&session);
+    info!("Dashboard login with API key {name}.");
+
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/dashboard")).into_response()
+}
+
+pub(crate) async fn dashboard_replay(
+    State(state): State<Arc<RwLock<AppState>>>,
+    origin: Origin,
+    Path(id): Path<i64>,
+) -> Response {
+    let entry = match db(&state).and_then(|db| db.get(id)) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            error!("Error occured: {err}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if !REPLAYABLE_ENDPOINTS.contains(&entry.endpoint.as_str()) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    // The replay can take a while, it shows up as pending job on the dashboard.
+    tokio::spawn(async move {
+        if let Err(err) = replay(state, entry, &origin).await {
+            error!("Error occured: {err}");
+        }
+    });
+
+    Redirect::to("/dashboard").into_response()
+}
+
+pub(crate) async fn dashboard_cancel(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<i64>,
+) -> Response {
+    match cancel_job(&state, id) {
+        Ok(true) => Redirect::to("/dashboard").into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            error!("Error occured: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(secure: bool) -> Sessions {
+        Sessions {
+            lifetime: Duration::from_secs(60 * 60),
+            secure,
+            sessions: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn sessions_expire() {
+        let mut sessions = sessions(false);
+        let id = sessions.create("admin", 1000);
+
+        assert_eq!(sessions.key_name(&id, 1000), Some("admin"));
+        assert_eq!(sessions.key_name(&id, 1000 + 60 * 60 - 1), Some("admin"));
+        assert_eq!(sessions.key_name(&id, 1000 + 60 * 60), None);
+        assert_eq!(sessions.key_name("unknown", 1000), None);
+
+        // ended sessions are dropped with the next login
+        let other = sessions.create("admin", 1000 + 60 * 60);
+        assert_eq!(sessions.sessions.len(), 1);
+        assert!(sessions.key_name(&other, 1000 + 60 * 60).is_some());
+    }
+
+    #[test]
+    fn session_cookie() {
+        let cookie = sessions(false).cookie("abc");
+        assert_eq!(
+            cookie,
+            "cross_seed_tools_session=abc; Path=/; Max-Age=3600; HttpOnly; SameSite=Strict"
+        );
+
+        let cookie = sessions(true).cookie("abc");
+        assert!(cookie.ends_with("; Secure"));
+        assert!(cookie.contains("Max-Age=3600"));
+    }
+}
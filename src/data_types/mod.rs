@@ -1,2 +1,72 @@
 pub mod radarr;
 pub mod sonarr;
+
+/// What a Sonarr / Radarr webhook means for cross-seeding, independent of the event names of
+/// each of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ArrEvent {
+    Test,
+    /// Release sent to the download client
+    Grab,
+    /// Import of a download
+    Import,
+    /// Import which replaced existing files
+    Upgrade,
+    /// File deleted from the library, e.g. manually or because of an upgrade
+    FileDelete,
+    /// Everything else, e.g. Rename or Health
+    #[default]
+    Other,
+}
+
+/// File removed from the library by an upgrade or a file delete event.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RemovedFile {
+    pub path: Option<String>,
+    /// Name of the release the file was imported from
+    pub scene_name: Option<String>,
+}
+
+/// A Sonarr / Radarr webhook reduced to what handling it needs. Every payload converts into it,
+/// so xseed and injection don't depend on where it came from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ArrImport {
+    pub event: ArrEvent,
+    /// `eventType` as sent
+    pub event_type: String,
+    pub instance_name: Option<String>,
+    pub download_client: Option<String>,
+    pub download_id: Option<String>,
+
+    pub release_title: Option<String>,
+    pub indexer: Option<String>,
+    pub release_type: Option<String>,
+
+    /// Imported file, or the folder of a season pack, cross-seed searches for
+    pub import_path: Option<String>,
+    /// Downloaded data the import was made from
+    pub source_path: Option<String>,
+    /// Files an upgrade replaced or the deleted file of a file delete event
+    pub removed_files: Vec<RemovedFile>,
+
+    pub poster: Option<String>,
+    /// Link to the series / movie
+    pub link: Option<String>,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    /// Parse a webhook fixture. Everything read has to survive a round trip, payloads are stored
+    /// and replayed.
+    pub(crate) fn parse<T: DeserializeOwned + Serialize>(fixture: &str) -> T {
+        let webhook: T = serde_json::from_str(fixture).unwrap();
+
+        let json = serde_json::to_string(&webhook).unwrap();
+        let again: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(json, serde_json::to_string(&again).unwrap());
+
+        webhook
+    }
+}
@@ -1,30 +1,532 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate) struct RadarrMovieFile {
-    pub path: String,
-    #[serde(rename = "sourcePath")]
-    pub source_path: String,
+use super::{ArrEvent, ArrImport, RemovedFile};
+
+/// Webhook event types of Radarr v5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RadarrEventType {
+    Test,
+    Grab,
+    /// Import of a download, `isUpgrade` tells if it replaced an existing file
+    Download,
+    Rename,
+    MovieAdded,
+    MovieDelete,
+    MovieFileDelete,
+    Health,
+    HealthRestored,
+    ApplicationUpdate,
+    ManualInteractionRequired,
+    /// Added by a newer Radarr version
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl RadarrEventType {
+    pub(crate) fn parse(event_type: &str) -> Self {
+        match event_type {
+            "Test" => RadarrEventType::Test,
+            "Grab" => RadarrEventType::Grab,
+            "Download" => RadarrEventType::Download,
+            "Rename" => RadarrEventType::Rename,
+            "MovieAdded" => RadarrEventType::MovieAdded,
+            "MovieDelete" => RadarrEventType::MovieDelete,
+            "MovieFileDelete" => RadarrEventType::MovieFileDelete,
+            "Health" => RadarrEventType::Health,
+            "HealthRestored" => RadarrEventType::HealthRestored,
+            "ApplicationUpdate" => RadarrEventType::ApplicationUpdate,
+            "ManualInteractionRequired" => RadarrEventType::ManualInteractionRequired,
+            _ => RadarrEventType::Unknown,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrLanguage {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+}
+
+/// The release grabbed, sent with Grab events and (with less fields) with imports.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct RadarrRelease {
-    #[serde(rename = "releaseTitle")]
-    pub release_title: String,
-    pub indexer: String,
+    pub release_title: Option<String>,
+    pub indexer: Option<String>,
+    pub quality: Option<String>,
+    pub quality_version: Option<i64>,
+    pub release_group: Option<String>,
+    pub size: Option<i64>,
+    pub custom_format_score: Option<i64>,
+    pub custom_formats: Option<Vec<String>>,
+    pub languages: Option<Vec<RadarrLanguage>>,
+    pub indexer_flags: Option<Vec<String>>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrMediaInfo {
+    pub audio_channels: Option<f64>,
+    pub audio_codec: Option<String>,
+    pub audio_languages: Option<Vec<String>>,
+    pub height: Option<i64>,
+    pub width: Option<i64>,
+    pub subtitles: Option<Vec<String>>,
+    pub video_codec: Option<String>,
+    pub video_dynamic_range: Option<String>,
+    pub video_dynamic_range_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrMovieFile {
+    pub id: Option<i64>,
+    pub relative_path: Option<String>,
+    pub path: Option<String>,
+    pub quality: Option<String>,
+    pub quality_version: Option<i64>,
+    pub release_group: Option<String>,
+    pub scene_name: Option<String>,
+    pub indexer_flags: Option<String>,
+    pub size: Option<i64>,
+    pub date_added: Option<String>,
+    pub languages: Option<Vec<RadarrLanguage>>,
+    pub media_info: Option<RadarrMediaInfo>,
+    /// Path of the file in the download folder
+    pub source_path: Option<String>,
+    /// Set for deleted files which were moved to the recycle bin
+    pub recycle_bin_path: Option<String>,
+    /// Set for renamed files
+    pub previous_relative_path: Option<String>,
+    /// Set for renamed files
+    pub previous_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrImage {
+    #[serde(default)]
+    pub cover_type: String,
+    pub url: Option<String>,
+    pub remote_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrMovie {
+    pub id: Option<i64>,
+    pub title: Option<String>,
+    pub year: Option<i64>,
+    pub release_date: Option<String>,
+    pub folder_path: Option<String>,
+    pub tmdb_id: Option<i64>,
+    pub imdb_id: Option<String>,
+    pub overview: Option<String>,
+    pub genres: Option<Vec<String>>,
+    pub images: Option<Vec<RadarrImage>>,
+    pub tags: Option<Vec<String>>,
+    pub original_language: Option<RadarrLanguage>,
+}
+
+/// The movie as parsed from the release name.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrRemoteMovie {
+    pub tmdb_id: Option<i64>,
+    pub imdb_id: Option<String>,
+    pub title: Option<String>,
+    pub year: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrCustomFormat {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrCustomFormatInfo {
+    pub custom_formats: Option<Vec<RadarrCustomFormat>>,
+    pub custom_format_score: Option<i64>,
+}
+
+/// Item in the download client, sent with ManualInteractionRequired events.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrDownloadInfo {
+    pub quality: Option<String>,
+    pub quality_version: Option<i64>,
+    pub title: Option<String>,
+    pub indexer: Option<String>,
+    pub size: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RadarrDownloadStatusMessage {
+    pub title: Option<String>,
+    pub messages: Option<Vec<String>>,
+}
+
+/// `deletedFiles` lists the replaced files of an upgrade, but is a flag for MovieDelete events.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub(crate) enum RadarrDeletedFiles {
+    Files(Vec<RadarrMovieFile>),
+    /// Whether the files were deleted together with the movie
+    MovieFiles(bool),
+}
+
+/// Payload of all Radarr v5 webhook events. Apart from `eventType` every field is optional, each
+/// event type only sends some of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct RadarrConnectWebhook {
-    #[serde(rename = "downloadClient")]
+    #[serde(default)]
+    pub event_type: String,
+    pub instance_name: Option<String>,
+    pub application_url: Option<String>,
+
+    pub movie: Option<RadarrMovie>,
+    pub remote_movie: Option<RadarrRemoteMovie>,
+    pub release: Option<RadarrRelease>,
+    pub custom_format_info: Option<RadarrCustomFormatInfo>,
+
     pub download_client: Option<String>,
-    #[serde(rename = "downloadId")]
+    pub download_client_type: Option<String>,
     pub download_id: Option<String>,
-    #[serde(rename = "eventType")]
-    pub event_type: String,
-    #[serde(rename = "movieFile")]
+
+    /// Imported file of a Download event or the deleted one of a MovieFileDelete event
     pub movie_file: Option<RadarrMovieFile>,
-    #[serde(rename = "sourcePath")]
     pub source_path: Option<String>,
-    pub release: Option<RadarrRelease>,
+    pub is_upgrade: Option<bool>,
+    pub deleted_files: Option<RadarrDeletedFiles>,
+    /// Reason of a MovieFileDelete event, e.g. `upgrade` or `manual`
+    pub delete_reason: Option<String>,
+    /// Size of the deleted folder of a MovieDelete event
+    pub movie_folder_size: Option<i64>,
+    /// How the movie of a MovieAdded event was added, e.g. `manual` or `list`
+    pub add_method: Option<String>,
+
+    pub renamed_movie_files: Option<Vec<RadarrMovieFile>>,
+
+    pub download_info: Option<RadarrDownloadInfo>,
+    pub download_status: Option<String>,
+    pub download_status_messages: Option<Vec<RadarrDownloadStatusMessage>>,
+
+    /// Health events
+    pub level: Option<String>,
+    pub message: Option<String>,
+    #[serde(rename = "type")]
+    pub health_type: Option<String>,
+    pub wiki_url: Option<String>,
+
+    /// ApplicationUpdate events
+    pub previous_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+impl RadarrConnectWebhook {
+    pub(crate) fn event(&self) -> RadarrEventType {
+        RadarrEventType::parse(&self.event_type)
+    }
+
+    fn deleted_by_upgrade(&self) -> bool {
+        self.delete_reason
+            .as_deref()
+            .is_some_and(|reason| reason.eq_ignore_ascii_case("upgrade"))
+    }
+
+    pub(crate) fn arr_event(&self) -> ArrEvent {
+        match self.event() {
+            RadarrEventType::Test => ArrEvent::Test,
+            RadarrEventType::Grab => ArrEvent::Grab,
+            RadarrEventType::Download if self.is_upgrade == Some(true) => ArrEvent::Upgrade,
+            RadarrEventType::Download => ArrEvent::Import,
+            // the replaced files are cleaned up with the upgrade, see `ARR_UPGRADE_CLEANUP`
+            RadarrEventType::MovieFileDelete if self.deleted_by_upgrade() => ArrEvent::Other,
+            RadarrEventType::MovieFileDelete => ArrEvent::FileDelete,
+            _ => ArrEvent::Other,
+        }
+    }
+
+    /// Files replaced by an upgrade or the deleted file of a MovieFileDelete event.
+    pub(crate) fn removed_files(&self) -> Vec<RemovedFile> {
+        let files = match (&self.deleted_files, &self.movie_file) {
+            (Some(RadarrDeletedFiles::Files(files)), _) => files.iter().collect(),
+            (_, Some(file)) if self.event() == RadarrEventType::MovieFileDelete => vec![file],
+            _ => Vec::new(),
+        };
+
+        files
+            .into_iter()
+            .map(|file| RemovedFile {
+                path: file.path.clone(),
+                scene_name: file.scene_name.clone(),
+            })
+            .collect()
+    }
+}
+
+impl From<&RadarrConnectWebhook> for ArrImport {
+    fn from(request: &RadarrConnectWebhook) -> Self {
+        let release = request.release.as_ref();
+        let movie = request.movie.as_ref();
+        // the movie parsed from the release name, for events without the library entry
+        let remote_movie = request.remote_movie.as_ref();
+        let imdb_id = movie
+            .and_then(|m| m.imdb_id.clone())
+            .or_else(|| remote_movie.and_then(|m| m.imdb_id.clone()));
+        let tmdb_id = movie
+            .and_then(|m| m.tmdb_id)
+            .or_else(|| remote_movie.and_then(|m| m.tmdb_id));
+
+        ArrImport {
+            event: request.arr_event(),
+            event_type: request.event_type.clone(),
+            instance_name: request.instance_name.clone(),
+            download_client: request.download_client.clone(),
+            download_id: request.download_id.clone(),
+            release_title: release.and_then(|r| r.release_title.clone()),
+            indexer: release.and_then(|r| r.indexer.clone()),
+            release_type: None,
+            import_path: request.movie_file.as_ref().and_then(|f| f.path.clone()),
+            source_path: request
+                .movie_file
+                .as_ref()
+                .and_then(|f| f.source_path.clone()),
+            removed_files: request.removed_files(),
+            poster: movie
+                .and_then(|m| m.images.as_ref())
+                .and_then(|images| images.iter().find(|i| i.cover_type == "poster"))
+                .and_then(|image| image.remote_url.clone()),
+            link: match (imdb_id, tmdb_id) {
+                (Some(imdb_id), _) if !imdb_id.is_empty() => {
+                    Some(format!("https://www.imdb.com/title/{imdb_id}"))
+                }
+                (_, Some(tmdb_id)) if tmdb_id > 0 => {
+                    Some(format!("https://www.themoviedb.org/movie/{tmdb_id}"))
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::tests::parse;
+
+    #[test]
+    fn test_event() {
+        let webhook: RadarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/radarr/test.json"));
+
+        assert_eq!(webhook.event(), RadarrEventType::Test);
+        assert_eq!(webhook.movie.unwrap().year, Some(1970));
+        assert_eq!(webhook.remote_movie.unwrap().tmdb_id, Some(1234));
+    }
+
+    #[test]
+    fn grab() {
+        let webhook: RadarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/radarr/grab.json"));
+
+        assert_eq!(webhook.event(), RadarrEventType::Grab);
+        let release = webhook.release.unwrap();
+        assert_eq!(
+            release.indexer.as_deref(),
+            Some("PassThePopcorn (Prowlarr)")
+        );
+        assert_eq!(release.custom_format_score, Some(2350));
+        assert_eq!(
+            release.indexer_flags.unwrap(),
+            ["G_Freeleech", "PTP_Golden"]
+        );
+
+        let movie = webhook.movie.unwrap();
+        assert_eq!(movie.title.as_deref(), Some("Dune: Part Two"));
+        assert_eq!(movie.year, Some(2024));
+        assert_eq!(movie.tmdb_id, Some(693134));
+        assert_eq!(movie.imdb_id.as_deref(), Some("tt15239678"));
+        assert_eq!(movie.images.unwrap()[0].cover_type, "poster");
+
+        assert_eq!(
+            webhook.remote_movie.unwrap().title.as_deref(),
+            Some("Dune Part Two")
+        );
+        let custom_formats = webhook.custom_format_info.unwrap().custom_formats.unwrap();
+        assert_eq!(custom_formats.len(), 3);
+    }
+
+    #[test]
+    fn download() {
+        let webhook: RadarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/radarr/download.json"));
+
+        assert_eq!(webhook.event(), RadarrEventType::Download);
+        assert_eq!(webhook.is_upgrade, Some(false));
+        assert!(webhook.deleted_files.is_none());
+
+        let movie_file = webhook.movie_file.unwrap();
+        assert!(movie_file
+            .source_path
+            .unwrap()
+            .starts_with("/downloads/movies/"));
+        assert_eq!(
+            movie_file.indexer_flags.as_deref(),
+            Some("G_Freeleech, PTP_Golden")
+        );
+        assert_eq!(movie_file.media_info.unwrap().audio_channels, Some(7.1));
+    }
+
+    #[test]
+    fn upgrade() {
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/download_upgrade.json"
+        ));
+
+        assert_eq!(webhook.is_upgrade, Some(true));
+        assert_eq!(webhook.arr_event(), ArrEvent::Upgrade);
+        assert_eq!(
+            webhook.removed_files()[0].scene_name.as_deref(),
+            Some("Arrival.2016.1080p.WEB-DL.DD5.1.H264-FGT")
+        );
+        let Some(RadarrDeletedFiles::Files(deleted)) = webhook.deleted_files else {
+            panic!("deletedFiles is not a list");
+        };
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(
+            deleted[0].path.as_deref(),
+            Some("/movies/Arrival (2016)/Arrival (2016) {imdb-tt2543164} [WEBDL-1080p][EAC3 5.1][h264]-FGT.mkv")
+        );
+        assert!(deleted[0].recycle_bin_path.is_some());
+        // files without indexer flags send the empty flags enum
+        assert_eq!(deleted[0].indexer_flags.as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn rename() {
+        let webhook: RadarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/radarr/rename.json"));
+
+        assert_eq!(webhook.event(), RadarrEventType::Rename);
+        let renamed = webhook.renamed_movie_files.unwrap();
+        assert_eq!(
+            renamed[0].previous_relative_path.as_deref(),
+            Some("Arrival (2016) {imdb-tt2543164} [Bluray-1080p][DTS 5.1][x264].mkv")
+        );
+        // imported without a release, Radarr leaves out what it doesn't know
+        assert!(renamed[0].scene_name.is_none());
+        assert!(renamed[0].release_group.is_none());
+    }
+
+    #[test]
+    fn movie_added() {
+        let webhook: RadarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/radarr/movie_added.json"));
+
+        assert_eq!(webhook.event(), RadarrEventType::MovieAdded);
+        assert_eq!(webhook.add_method.as_deref(), Some("list"));
+    }
+
+    #[test]
+    fn movie_delete() {
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/movie_delete.json"
+        ));
+
+        assert_eq!(webhook.event(), RadarrEventType::MovieDelete);
+        assert_eq!(webhook.arr_event(), ArrEvent::Other);
+        assert!(webhook.removed_files().is_empty());
+        assert!(matches!(
+            webhook.deleted_files,
+            Some(RadarrDeletedFiles::MovieFiles(true))
+        ));
+        assert_eq!(webhook.movie_folder_size, Some(14567890123));
+    }
+
+    #[test]
+    fn movie_file_delete() {
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/movie_file_delete.json"
+        ));
+
+        assert_eq!(webhook.event(), RadarrEventType::MovieFileDelete);
+        assert_eq!(webhook.arr_event(), ArrEvent::Other);
+        // deleted by an upgrade, left to the upgrade cleanup
+        assert_eq!(ArrImport::from(&webhook).event, ArrEvent::Other);
+        assert_eq!(webhook.removed_files().len(), 1);
+        assert_eq!(webhook.delete_reason.as_deref(), Some("upgrade"));
+        assert_eq!(webhook.movie_file.unwrap().id, Some(1207));
+    }
+
+    #[test]
+    fn movie_file_delete_manual() {
+        let mut webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/movie_file_delete.json"
+        ));
+        webhook.delete_reason = Some("manual".to_string());
+
+        assert_eq!(webhook.arr_event(), ArrEvent::FileDelete);
+        assert_eq!(ArrImport::from(&webhook).event, ArrEvent::FileDelete);
+    }
+
+    #[test]
+    fn health() {
+        let webhook: RadarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/radarr/health.json"));
+        assert_eq!(webhook.event(), RadarrEventType::Health);
+        assert_eq!(webhook.health_type.as_deref(), Some("DownloadClientCheck"));
+
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/health_restored.json"
+        ));
+        assert_eq!(webhook.event(), RadarrEventType::HealthRestored);
+        assert!(webhook.movie.is_none());
+    }
+
+    #[test]
+    fn application_update() {
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/application_update.json"
+        ));
+
+        assert_eq!(webhook.event(), RadarrEventType::ApplicationUpdate);
+        assert_eq!(webhook.previous_version.as_deref(), Some("5.15.1.9463"));
+        assert_eq!(webhook.new_version.as_deref(), Some("5.16.3.9541"));
+    }
+
+    #[test]
+    fn manual_interaction_required() {
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/manual_interaction_required.json"
+        ));
+
+        assert_eq!(webhook.event(), RadarrEventType::ManualInteractionRequired);
+        assert_eq!(webhook.download_status.as_deref(), Some("Warning"));
+        assert_eq!(
+            webhook.download_info.unwrap().indexer.as_deref(),
+            Some("TorrentLeech (Prowlarr)")
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        let webhook: RadarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/radarr/download_minimal.json"
+        ));
+
+        assert_eq!(webhook.event(), RadarrEventType::Download);
+        assert!(webhook.release.unwrap().indexer.is_none());
+        assert!(webhook.movie.is_none());
+        assert!(webhook.movie_file.unwrap().source_path.is_none());
+
+        let webhook: RadarrConnectWebhook =
+            serde_json::from_str(r#"{"eventType": "SomethingNew"}"#).unwrap();
+        assert_eq!(webhook.event(), RadarrEventType::Unknown);
+    }
 }
@@ -1,34 +1,530 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use super::{ArrEvent, ArrImport, RemovedFile};
+
+/// Webhook event types of Sonarr v4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SonarrEventType {
+    Test,
+    Grab,
+    /// Import of a download, `isUpgrade` tells if it replaced existing files
+    Download,
+    Rename,
+    SeriesAdd,
+    SeriesDelete,
+    EpisodeFileDelete,
+    Health,
+    HealthRestored,
+    ApplicationUpdate,
+    ManualInteractionRequired,
+    /// Added by a newer Sonarr version
+    Unknown,
+}
+
+impl SonarrEventType {
+    pub(crate) fn parse(event_type: &str) -> Self {
+        match event_type {
+            "Test" => SonarrEventType::Test,
+            "Grab" => SonarrEventType::Grab,
+            "Download" => SonarrEventType::Download,
+            "Rename" => SonarrEventType::Rename,
+            "SeriesAdd" => SonarrEventType::SeriesAdd,
+            "SeriesDelete" => SonarrEventType::SeriesDelete,
+            "EpisodeFileDelete" => SonarrEventType::EpisodeFileDelete,
+            "Health" => SonarrEventType::Health,
+            "HealthRestored" => SonarrEventType::HealthRestored,
+            "ApplicationUpdate" => SonarrEventType::ApplicationUpdate,
+            "ManualInteractionRequired" => SonarrEventType::ManualInteractionRequired,
+            _ => SonarrEventType::Unknown,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrLanguage {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+}
+
+/// The release grabbed, sent with Grab events and (with less fields) with imports.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct SonarrRelease {
-    pub indexer: String,
-    #[serde(rename = "releaseTitle")]
-    pub release_title: String,
-    #[serde(rename = "releaseType")]
-    pub release_type: String,
+    pub indexer: Option<String>,
+    pub release_title: Option<String>,
+    /// `SingleEpisode`, `MultiEpisode` or `SeasonPack`
+    pub release_type: Option<String>,
+    pub quality: Option<String>,
+    pub quality_version: Option<i64>,
+    pub release_group: Option<String>,
+    pub size: Option<i64>,
+    pub custom_format_score: Option<i64>,
+    pub custom_formats: Option<Vec<String>>,
+    pub languages: Option<Vec<SonarrLanguage>>,
+    pub indexer_flags: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrMediaInfo {
+    pub audio_channels: Option<f64>,
+    pub audio_codec: Option<String>,
+    pub audio_languages: Option<Vec<String>>,
+    pub height: Option<i64>,
+    pub width: Option<i64>,
+    pub subtitles: Option<Vec<String>>,
+    pub video_codec: Option<String>,
+    pub video_dynamic_range: Option<String>,
+    pub video_dynamic_range_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct SonarrEpisodeFile {
-    pub path: String,
+    pub id: Option<i64>,
+    pub relative_path: Option<String>,
+    pub path: Option<String>,
+    pub quality: Option<String>,
+    pub quality_version: Option<i64>,
+    pub release_group: Option<String>,
+    pub scene_name: Option<String>,
+    pub size: Option<i64>,
+    pub date_added: Option<String>,
+    pub languages: Option<Vec<SonarrLanguage>>,
+    pub media_info: Option<SonarrMediaInfo>,
+    /// Path of the file in the download folder
+    pub source_path: Option<String>,
+    /// Set for deleted files which were moved to the recycle bin
+    pub recycle_bin_path: Option<String>,
+    /// Set for renamed files
+    pub previous_relative_path: Option<String>,
+    /// Set for renamed files
+    pub previous_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrEpisode {
+    pub id: Option<i64>,
+    pub episode_number: Option<i64>,
+    pub season_number: Option<i64>,
+    pub title: Option<String>,
+    pub overview: Option<String>,
+    pub air_date: Option<String>,
+    pub air_date_utc: Option<String>,
+    pub series_id: Option<i64>,
+    pub tvdb_id: Option<i64>,
+    pub finale_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrImage {
+    #[serde(default)]
+    pub cover_type: String,
+    pub url: Option<String>,
+    pub remote_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrSeries {
+    pub id: Option<i64>,
+    pub title: Option<String>,
+    pub title_slug: Option<String>,
+    pub path: Option<String>,
+    pub tvdb_id: Option<i64>,
+    pub tv_maze_id: Option<i64>,
+    pub tmdb_id: Option<i64>,
+    pub imdb_id: Option<String>,
+    /// `standard`, `daily` or `anime`
+    #[serde(rename = "type")]
+    pub series_type: Option<String>,
+    pub year: Option<i64>,
+    pub genres: Option<Vec<String>>,
+    pub images: Option<Vec<SonarrImage>>,
+    pub tags: Option<Vec<String>>,
+    pub original_language: Option<SonarrLanguage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrCustomFormat {
+    pub id: Option<i64>,
+    pub name: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrCustomFormatInfo {
+    pub custom_formats: Option<Vec<SonarrCustomFormat>>,
+    pub custom_format_score: Option<i64>,
+}
+
+/// Item in the download client, sent with ManualInteractionRequired events.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrDownloadInfo {
+    pub quality: Option<String>,
+    pub quality_version: Option<i64>,
+    pub title: Option<String>,
+    pub indexer: Option<String>,
+    pub size: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SonarrDownloadStatusMessage {
+    pub title: Option<String>,
+    pub messages: Option<Vec<String>>,
+}
+
+/// `deletedFiles` lists the replaced files of an upgrade, but is a flag for SeriesDelete events.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub(crate) enum SonarrDeletedFiles {
+    Files(Vec<SonarrEpisodeFile>),
+    /// Whether the files were deleted together with the series
+    SeriesFiles(bool),
+}
+
+/// Payload of all Sonarr v4 webhook events. Apart from `eventType` every field is optional, each
+/// event type only sends some of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct SonarrConnectWebhook {
-    #[serde(rename = "destinationPath")]
-    pub destination_path: Option<String>,
-    #[serde(rename = "instanceName")]
+    #[serde(default)]
+    pub event_type: String,
     pub instance_name: Option<String>,
-    #[serde(rename = "downloadClient")]
+    pub application_url: Option<String>,
+
+    pub series: Option<SonarrSeries>,
+    pub episodes: Option<Vec<SonarrEpisode>>,
+    pub release: Option<SonarrRelease>,
+    pub custom_format_info: Option<SonarrCustomFormatInfo>,
+
     pub download_client: Option<String>,
-    #[serde(rename = "downloadId")]
+    pub download_client_type: Option<String>,
     pub download_id: Option<String>,
-    #[serde(rename = "eventType")]
-    pub event_type: String,
-    #[serde(rename = "episodeFiles")]
+
+    /// Imported file of a Download event or the deleted one of an EpisodeFileDelete event
+    pub episode_file: Option<SonarrEpisodeFile>,
+    /// All files of an import, e.g. of a season pack
     pub episode_files: Option<Vec<SonarrEpisodeFile>>,
-    pub release: Option<SonarrRelease>,
-    #[serde(rename = "sourcePath")]
     pub source_path: Option<String>,
+    pub destination_path: Option<String>,
+    pub is_upgrade: Option<bool>,
+    pub deleted_files: Option<SonarrDeletedFiles>,
+    /// Reason of an EpisodeFileDelete event, e.g. `upgrade` or `manual`
+    pub delete_reason: Option<String>,
+
+    pub renamed_episode_files: Option<Vec<SonarrEpisodeFile>>,
+
+    pub download_info: Option<SonarrDownloadInfo>,
+    pub download_status: Option<String>,
+    pub download_status_messages: Option<Vec<SonarrDownloadStatusMessage>>,
+
+    /// Health events
+    pub level: Option<String>,
+    pub message: Option<String>,
+    #[serde(rename = "type")]
+    pub health_type: Option<String>,
+    pub wiki_url: Option<String>,
+
+    /// ApplicationUpdate events
+    pub previous_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+impl SonarrConnectWebhook {
+    pub(crate) fn event(&self) -> SonarrEventType {
+        SonarrEventType::parse(&self.event_type)
+    }
+
+    fn deleted_by_upgrade(&self) -> bool {
+        self.delete_reason
+            .as_deref()
+            .is_some_and(|reason| reason.eq_ignore_ascii_case("upgrade"))
+    }
+
+    pub(crate) fn arr_event(&self) -> ArrEvent {
+        match self.event() {
+            SonarrEventType::Test => ArrEvent::Test,
+            SonarrEventType::Grab => ArrEvent::Grab,
+            SonarrEventType::Download if self.is_upgrade == Some(true) => ArrEvent::Upgrade,
+            SonarrEventType::Download => ArrEvent::Import,
+            // the replaced files are cleaned up with the upgrade, see `ARR_UPGRADE_CLEANUP`
+            SonarrEventType::EpisodeFileDelete if self.deleted_by_upgrade() => ArrEvent::Other,
+            SonarrEventType::EpisodeFileDelete => ArrEvent::FileDelete,
+            _ => ArrEvent::Other,
+        }
+    }
+
+    /// Files replaced by an upgrade or the deleted file of an EpisodeFileDelete event.
+    pub(crate) fn removed_files(&self) -> Vec<RemovedFile> {
+        let files = match (&self.deleted_files, &self.episode_file) {
+            (Some(SonarrDeletedFiles::Files(files)), _) => files.iter().collect(),
+            (_, Some(file)) if self.event() == SonarrEventType::EpisodeFileDelete => vec![file],
+            _ => Vec::new(),
+        };
+
+        files
+            .into_iter()
+            .map(|file| RemovedFile {
+                path: file.path.clone(),
+                scene_name: file.scene_name.clone(),
+            })
+            .collect()
+    }
+}
+
+impl From<&SonarrConnectWebhook> for ArrImport {
+    fn from(request: &SonarrConnectWebhook) -> Self {
+        let release = request.release.as_ref();
+        let release_type = release.and_then(|r| r.release_type.clone());
+        let series = request.series.as_ref();
+
+        // cross-seed matches season packs by their folder
+        let import_path = if release_type
+            .as_deref()
+            .is_some_and(|release_type| release_type.eq_ignore_ascii_case("seasonpack"))
+        {
+            request.destination_path.clone()
+        } else {
+            request
+                .episode_files
+                .as_ref()
+                .and_then(|files| files.first())
+                .and_then(|file| file.path.clone())
+        };
+
+        ArrImport {
+            event: request.arr_event(),
+            event_type: request.event_type.clone(),
+            instance_name: request.instance_name.clone(),
+            download_client: request.download_client.clone(),
+            download_id: request.download_id.clone(),
+            release_title: release.and_then(|r| r.release_title.clone()),
+            indexer: release.and_then(|r| r.indexer.clone()),
+            release_type,
+            import_path,
+            source_path: request.source_path.clone(),
+            removed_files: request.removed_files(),
+            poster: series
+                .and_then(|s| s.images.as_ref())
+                .and_then(|images| images.iter().find(|i| i.cover_type == "poster"))
+                .and_then(|image| image.remote_url.clone()),
+            link: series.and_then(|s| match (&s.imdb_id, s.tvdb_id) {
+                (Some(imdb_id), _) if !imdb_id.is_empty() => {
+                    Some(format!("https://www.imdb.com/title/{imdb_id}"))
+                }
+                (_, Some(tvdb_id)) if tvdb_id > 0 => {
+                    Some(format!("https://www.thetvdb.com/?tab=series&id={tvdb_id}"))
+                }
+                _ => None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::tests::parse;
+
+    #[test]
+    fn test_event() {
+        let webhook: SonarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/sonarr/test.json"));
+
+        assert_eq!(webhook.event(), SonarrEventType::Test);
+        assert!(webhook.release.is_none());
+        assert_eq!(webhook.series.unwrap().tvdb_id, Some(1234));
+    }
+
+    #[test]
+    fn grab() {
+        let webhook: SonarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/sonarr/grab.json"));
+
+        assert_eq!(webhook.event(), SonarrEventType::Grab);
+        let release = webhook.release.unwrap();
+        assert_eq!(release.indexer.as_deref(), Some("TorrentLeech (Prowlarr)"));
+        assert_eq!(release.release_type.as_deref(), Some("SingleEpisode"));
+        assert_eq!(release.custom_format_score, Some(1750));
+        assert_eq!(release.indexer_flags.unwrap(), ["G_Freeleech"]);
+
+        let series = webhook.series.unwrap();
+        assert_eq!(series.series_type.as_deref(), Some("standard"));
+        assert_eq!(series.images.unwrap()[1].cover_type, "poster");
+
+        let custom_formats = webhook.custom_format_info.unwrap().custom_formats.unwrap();
+        assert_eq!(custom_formats.len(), 3);
+        assert_eq!(
+            webhook.download_id.as_deref(),
+            Some("4F7C3C6D8A1B2E3F4A5B6C7D8E9F0A1B2C3D4E5F")
+        );
+    }
+
+    #[test]
+    fn download() {
+        let webhook: SonarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/sonarr/download.json"));
+
+        assert_eq!(webhook.event(), SonarrEventType::Download);
+        assert_eq!(webhook.arr_event(), ArrEvent::Import);
+        assert!(webhook.removed_files().is_empty());
+        assert_eq!(webhook.is_upgrade, Some(false));
+        assert!(webhook.deleted_files.is_none());
+
+        let episode_file = webhook.episode_file.unwrap();
+        let media_info = episode_file.media_info.unwrap();
+        assert_eq!(media_info.audio_channels, Some(5.1));
+        assert_eq!(media_info.height, Some(2160));
+        assert_eq!(
+            webhook.episode_files.unwrap()[0].path.as_deref(),
+            Some("/tv/Severance/Season 02/Severance - S02E03 - Who Is Alive.mkv")
+        );
+        assert_eq!(
+            webhook.destination_path.as_deref(),
+            Some("/tv/Severance/Season 02")
+        );
+    }
+
+    #[test]
+    fn season_pack_upgrade() {
+        let webhook: SonarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/sonarr/download_season_pack_upgrade.json"
+        ));
+
+        assert_eq!(webhook.is_upgrade, Some(true));
+        assert_eq!(webhook.arr_event(), ArrEvent::Upgrade);
+        assert_eq!(webhook.removed_files().len(), 2);
+        assert_eq!(
+            webhook.release.unwrap().release_type.as_deref(),
+            Some("SeasonPack")
+        );
+        assert_eq!(webhook.episode_files.unwrap().len(), 2);
+
+        let Some(SonarrDeletedFiles::Files(deleted)) = webhook.deleted_files else {
+            panic!("deletedFiles is not a list");
+        };
+        assert_eq!(deleted.len(), 2);
+        assert_eq!(
+            deleted[0].scene_name.as_deref(),
+            Some("The.Bear.S01E01.720p.HDTV.x264-TEPES")
+        );
+        assert!(deleted[0].recycle_bin_path.is_some());
+    }
+
+    #[test]
+    fn rename() {
+        let webhook: SonarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/sonarr/rename.json"));
+
+        assert_eq!(webhook.event(), SonarrEventType::Rename);
+        let renamed = webhook.renamed_episode_files.unwrap();
+        assert_eq!(
+            renamed[0].previous_path.as_deref(),
+            Some("/tv/The Bear/Season 01/The Bear - S01E01 - System.mkv")
+        );
+    }
+
+    #[test]
+    fn series_delete() {
+        let webhook: SonarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/sonarr/series_delete.json"
+        ));
+
+        assert_eq!(webhook.event(), SonarrEventType::SeriesDelete);
+        assert_eq!(webhook.arr_event(), ArrEvent::Other);
+        assert!(webhook.removed_files().is_empty());
+        assert!(matches!(
+            webhook.deleted_files,
+            Some(SonarrDeletedFiles::SeriesFiles(true))
+        ));
+    }
+
+    #[test]
+    fn episode_file_delete() {
+        let webhook: SonarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/sonarr/episode_file_delete.json"
+        ));
+
+        assert_eq!(webhook.event(), SonarrEventType::EpisodeFileDelete);
+        assert_eq!(webhook.arr_event(), ArrEvent::Other);
+        // deleted by an upgrade, left to the upgrade cleanup
+        assert_eq!(ArrImport::from(&webhook).event, ArrEvent::Other);
+        assert_eq!(
+            webhook.removed_files()[0].scene_name.as_deref(),
+            Some("The.Bear.S01E01.720p.HDTV.x264-TEPES")
+        );
+        assert_eq!(webhook.delete_reason.as_deref(), Some("upgrade"));
+        assert_eq!(webhook.episode_file.unwrap().id, Some(801));
+    }
+
+    #[test]
+    fn episode_file_delete_manual() {
+        let mut webhook: SonarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/sonarr/episode_file_delete.json"
+        ));
+        webhook.delete_reason = Some("manual".to_string());
+
+        assert_eq!(webhook.arr_event(), ArrEvent::FileDelete);
+        assert_eq!(ArrImport::from(&webhook).event, ArrEvent::FileDelete);
+    }
+
+    #[test]
+    fn health() {
+        let webhook: SonarrConnectWebhook =
+            parse(include_str!("../../tests/fixtures/sonarr/health.json"));
+
+        assert_eq!(webhook.event(), SonarrEventType::Health);
+        assert_eq!(webhook.level.as_deref(), Some("warning"));
+        assert_eq!(
+            webhook.health_type.as_deref(),
+            Some("IndexerLongTermStatusCheck")
+        );
+        assert!(webhook.series.is_none());
+    }
+
+    #[test]
+    fn manual_interaction_required() {
+        let webhook: SonarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/sonarr/manual_interaction_required.json"
+        ));
+
+        assert_eq!(webhook.event(), SonarrEventType::ManualInteractionRequired);
+        assert_eq!(webhook.download_status.as_deref(), Some("Warning"));
+        assert_eq!(
+            webhook.download_info.unwrap().title.as_deref(),
+            Some("Severance.S02E04.2160p.WEB.H265-SuccessfulCrab")
+        );
+        assert_eq!(
+            webhook.download_status_messages.unwrap()[0]
+                .messages
+                .as_ref()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        let webhook: SonarrConnectWebhook = parse(include_str!(
+            "../../tests/fixtures/sonarr/download_minimal.json"
+        ));
+
+        assert_eq!(webhook.event(), SonarrEventType::Download);
+        let release = webhook.release.unwrap();
+        assert!(release.indexer.is_none());
+        assert!(release.release_type.is_none());
+        assert!(webhook.series.is_none());
+
+        let webhook: SonarrConnectWebhook =
+            serde_json::from_str(r#"{"eventType": "SomethingNew"}"#).unwrap();
+        assert_eq!(webhook.event(), SonarrEventType::Unknown);
+    }
 }
@@ -0,0 +1,860 @@
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+use anyhow::anyhow;
+
+use serde::{Deserialize, Serialize};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+};
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task::block_in_place;
+
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row};
+
+use crate::auth::KeyName;
+use crate::data_types::ArrImport;
+use crate::logging::RequestId;
+use crate::AppState;
+
+const SCHEMA_V1: &str = "
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    endpoint TEXT NOT NULL,
+    request_id TEXT,
+    received_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    status TEXT NOT NULL,
+    release_title TEXT,
+    download_id TEXT,
+    download_client TEXT,
+    error TEXT,
+    payload TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS history_received_at ON history(received_at);
+
+CREATE TABLE IF NOT EXISTS history_calls (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    history_id INTEGER NOT NULL REFERENCES history(id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    action TEXT NOT NULL,
+    success INTEGER NOT NULL,
+    detail TEXT
+);
+CREATE INDEX IF NOT EXISTS history_calls_history_id ON history_calls(history_id);
+";
+
+const SCHEMA_V2: &str = "
+ALTER TABLE history ADD COLUMN replay_of INTEGER REFERENCES history(id);
+";
+
+const SCHEMA_V3: &str = "
+ALTER TABLE history ADD COLUMN api_key TEXT;
+";
+
+const SCHEMA_V4: &str = "
+ALTER TABLE history ADD COLUMN dry_run INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Schema migrations, applied in order based on sqlite's `user_version`.
+const MIGRATIONS: &[&str] = &[SCHEMA_V1, SCHEMA_V2, SCHEMA_V3, SCHEMA_V4];
+
+const HISTORY_COLUMNS: &str = "id, endpoint, request_id, received_at, finished_at, status, \
+                               release_title, download_id, download_client, error, payload, \
+                               replay_of, api_key, dry_run";
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    Running,
+    Success,
+    Failed,
+    Cancelled,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Running => "running",
+            Status::Success => "success",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(status: &str) -> Status {
+        match status {
+            "running" => Status::Running,
+            "success" => Status::Success,
+            "cancelled" => Status::Cancelled,
+            _ => Status::Failed,
+        }
+    }
+}
+
+/// Where a request came from, stored with its history entry.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Origin {
+    pub request_id: Option<String>,
+    /// Name of the API key used
+    pub api_key: Option<String>,
+    /// Only pretend to make outbound calls which change something
+    pub dry_run: bool,
+}
+
+const DRY_RUN_HEADER: &str = "X-Dry-Run";
+
+fn global_dry_run(state: &Arc<RwLock<AppState>>) -> bool {
+    state.read().map(|state| state.dry_run).unwrap_or_default()
+}
+
+impl Origin {
+    /// Origin of requests started from the command line.
+    pub(crate) fn command_line(state: &Arc<RwLock<AppState>>) -> Self {
+        Origin {
+            dry_run: global_dry_run(state),
+            ..Default::default()
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for Origin
+where
+    S: Send + Sync,
+    Arc<RwLock<AppState>>: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let dry_run_header = parts
+            .headers
+            .get(DRY_RUN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| matches!(value.to_lowercase().as_str(), "true" | "1" | "yes"));
+
+        Ok(Origin {
+            dry_run: dry_run_header || global_dry_run(&Arc::from_ref(state)),
+            request_id: parts
+                .extensions
+                .get::<RequestId>()
+                .map(|RequestId(id)| id.clone()),
+            api_key: parts
+                .extensions
+                .get::<KeyName>()
+                .map(|KeyName(name)| name.clone()),
+        })
+    }
+}
+
+/// Key fields of an incoming webhook which are stored in separate columns so they can be
+/// filtered on.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EntryFields {
+    pub release_title: Option<String>,
+    pub download_id: Option<String>,
+    pub download_client: Option<String>,
+    /// Id of the entry this request is a replay of.
+    pub replay_of: Option<i64>,
+}
+
+impl From<&ArrImport> for EntryFields {
+    fn from(request: &ArrImport) -> Self {
+        EntryFields {
+            release_title: request.release_title.clone(),
+            download_id: request.download_id.clone(),
+            download_client: request.download_client.clone(),
+            replay_of: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct OutboundCall {
+    pub created_at: i64,
+    pub target: String,
+    pub action: String,
+    pub success: bool,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct HistoryEntry {
+    pub id: i64,
+    pub endpoint: String,
+    pub request_id: Option<String>,
+    pub received_at: i64,
+    pub finished_at: Option<i64>,
+    pub status: Status,
+    pub release_title: Option<String>,
+    pub download_id: Option<String>,
+    pub download_client: Option<String>,
+    pub error: Option<String>,
+    pub payload: serde_json::Value,
+    pub replay_of: Option<i64>,
+    pub api_key: Option<String>,
+    pub dry_run: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calls: Option<Vec<OutboundCall>>,
+}
+
+impl HistoryEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let status: String = row.get(5)?;
+        let payload: String = row.get(10)?;
+
+        Ok(HistoryEntry {
+            id: row.get(0)?,
+            endpoint: row.get(1)?,
+            request_id: row.get(2)?,
+            received_at: row.get(3)?,
+            finished_at: row.get(4)?,
+            status: Status::parse(&status),
+            release_title: row.get(6)?,
+            download_id: row.get(7)?,
+            download_client: row.get(8)?,
+            error: row.get(9)?,
+            payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::Null),
+            replay_of: row.get(11)?,
+            api_key: row.get(12)?,
+            dry_run: row.get(13)?,
+            calls: None,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct HistoryFilter {
+    /// One or more endpoints, separated by commas
+    pub endpoint: Option<String>,
+    pub release_title: Option<String>,
+    pub download_id: Option<String>,
+    pub status: Option<Status>,
+    /// Unix timestamp (seconds)
+    pub since: Option<i64>,
+    /// Unix timestamp (seconds)
+    pub until: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+/// Counts of the requests handled in a time range, dry runs excluded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HistorySummary {
+    /// Announces accepted per cross-seed instance
+    pub announces_accepted: Vec<(String, i64)>,
+    /// Requests per endpoint and status
+    pub requests: Vec<(String, Status, i64)>,
+}
+
+/// Run the blocking database call `f`. Within the server's runtime other tasks are moved off the
+/// worker thread meanwhile, so slow disks don't hold up the handling of requests.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => block_in_place(f),
+        _ => f(),
+    }
+}
+
+/// Local sqlite database storing the history of all handled webhooks.
+pub(crate) struct Db {
+    conn: Mutex<Connection>,
+}
+
+impl Db {
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = conn.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+        }
+
+        Ok(Db {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Mark the entries still running as failed, they were interrupted by a restart. Only the
+    /// server may do this on startup, commands run next to it would fail its running requests.
+    pub(crate) fn fail_interrupted(&self) -> anyhow::Result<()> {
+        let interrupted = self.conn()?.execute(
+            "UPDATE history SET status = ?1, finished_at = ?2, error = ?3 WHERE status = ?4",
+            params![
+                Status::Failed.as_str(),
+                now(),
+                "Interrupted by a restart.",
+                Status::Running.as_str()
+            ],
+        )?;
+        if interrupted > 0 {
+            warn!("Marked {interrupted} interrupted requests as failed.");
+        }
+
+        Ok(())
+    }
+
+    fn conn(&self) -> anyhow::Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| anyhow!("Could not lock the database."))
+    }
+
+    /// Store a new incoming request and return a `Record` to track its progress.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        endpoint: &str,
+        origin: &Origin,
+        fields: EntryFields,
+        payload: &impl Serialize,
+    ) -> anyhow::Result<Record> {
+        let payload = serde_json::to_string(payload)?;
+        let id = blocking(|| {
+            let conn = self.conn()?;
+            conn.execute(
+                "INSERT INTO history (endpoint, request_id, received_at, status, release_title, \
+                 download_id, download_client, payload, replay_of, api_key, dry_run) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    endpoint,
+                    origin.request_id,
+                    now(),
+                    Status::Running.as_str(),
+                    fields.release_title,
+                    fields.download_id,
+                    fields.download_client,
+                    payload,
+                    fields.replay_of,
+                    origin.api_key,
+                    origin.dry_run
+                ],
+            )?;
+            anyhow::Ok(conn.last_insert_rowid())
+        })?;
+
+        Ok(Record {
+            entry: Some((self.clone(), id)),
+            dry_run: origin.dry_run,
+        })
+    }
+
+    pub(crate) fn list(&self, filter: &HistoryFilter) -> anyhow::Result<Vec<HistoryEntry>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(endpoint) = &filter.endpoint {
+            let endpoints = endpoint.split(',').map(str::trim).collect::<Vec<_>>();
+            conditions.push(format!(
+                "endpoint IN ({})",
+                vec!["?"; endpoints.len()].join(", ")
+            ));
+            values.extend(endpoints.into_iter().map(|e| e.to_string().into()));
+        }
+        if let Some(release_title) = &filter.release_title {
+            conditions.push("release_title LIKE '%' || ? || '%'".to_string());
+            values.push(release_title.clone().into());
+        }
+        if let Some(download_id) = &filter.download_id {
+            conditions.push("download_id = ?".to_string());
+            values.push(download_id.clone().into());
+        }
+        if let Some(status) = &filter.status {
+            conditions.push("status = ?".to_string());
+            values.push(status.as_str().to_string().into());
+        }
+        if let Some(since) = filter.since {
+            conditions.push("received_at >= ?".to_string());
+            values.push(since.into());
+        }
+        if let Some(until) = filter.until {
+            conditions.push("received_at <= ?".to_string());
+            values.push(until.into());
+        }
+
+        let mut query = format!("SELECT {HISTORY_COLUMNS} FROM history");
+        if !conditions.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&conditions.join(" AND "));
+        }
+        query.push_str(" ORDER BY id DESC LIMIT ?");
+        values.push(i64::from(filter.limit.unwrap_or(100)).into());
+
+        blocking(|| {
+            let conn = self.conn()?;
+            let mut statement = conn.prepare(&query)?;
+            let entries = statement
+                .query_map(params_from_iter(values), HistoryEntry::from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            Ok(entries)
+        })
+    }
+
+    /// Whether a replay of the given entry already succeeded.
+    pub(crate) fn replayed_successfully(&self, id: i64) -> anyhow::Result<bool> {
+        blocking(|| {
+            let conn = self.conn()?;
+            let replayed = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM history WHERE replay_of = ?1 AND status = ?2 \
+             AND dry_run = 0)",
+                params![id, Status::Success.as_str()],
+                |row| row.get(0),
+            )?;
+
+            Ok(replayed)
+        })
+    }
+
+    /// Number of failed replays of the given entry, dry runs excluded.
+    pub(crate) fn failed_replays(&self, id: i64) -> anyhow::Result<u32> {
+        blocking(|| {
+            let conn = self.conn()?;
+            let failed = conn.query_row(
+                "SELECT COUNT(*) FROM history WHERE replay_of = ?1 AND status = ?2 \
+                 AND dry_run = 0",
+                params![id, Status::Failed.as_str()],
+                |row| row.get(0),
+            )?;
+
+            Ok(failed)
+        })
+    }
+
+    /// Summary of all requests received since `since` (Unix timestamp).
+    pub(crate) fn summary(&self, since: i64) -> anyhow::Result<HistorySummary> {
+        blocking(|| {
+            let conn = self.conn()?;
+
+            let mut statement = conn.prepare(
+            "SELECT c.target, COUNT(*) FROM history_calls c JOIN history h ON h.id = c.history_id \
+             WHERE h.endpoint = '/announce' AND c.action = 'announce' AND c.success = 1 \
+             AND h.received_at >= ?1 AND h.dry_run = 0 GROUP BY c.target ORDER BY c.target",
+        )?;
+            let announces_accepted = statement
+                .query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            let mut statement = conn.prepare(
+                "SELECT endpoint, status, COUNT(*) FROM history WHERE received_at >= ?1 \
+             AND dry_run = 0 GROUP BY endpoint, status ORDER BY endpoint, status",
+            )?;
+            let requests = statement
+                .query_map(params![since], |row| {
+                    let status: String = row.get(1)?;
+                    Ok((row.get(0)?, Status::parse(&status), row.get(2)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            Ok(HistorySummary {
+                announces_accepted,
+                requests,
+            })
+        })
+    }
+
+    pub(crate) fn get(&self, id: i64) -> anyhow::Result<Option<HistoryEntry>> {
+        let entry = blocking(|| {
+            self.conn()?
+                .query_row(
+                    &format!("SELECT {HISTORY_COLUMNS} FROM history WHERE id = ?1"),
+                    params![id],
+                    HistoryEntry::from_row,
+                )
+                .optional()
+                .map_err(anyhow::Error::from)
+        })?;
+
+        let Some(mut entry) = entry else {
+            return Ok(None);
+        };
+
+        entry.calls = Some(self.calls(id)?);
+
+        Ok(Some(entry))
+    }
+
+    /// All outbound calls made while handling the given entry.
+    pub(crate) fn calls(&self, id: i64) -> anyhow::Result<Vec<OutboundCall>> {
+        blocking(|| {
+            let conn = self.conn()?;
+            let mut statement = conn.prepare(
+                "SELECT created_at, target, action, success, detail FROM history_calls \
+             WHERE history_id = ?1 ORDER BY id",
+            )?;
+            let calls = statement
+                .query_map(params![id], |row| {
+                    Ok(OutboundCall {
+                        created_at: row.get(0)?,
+                        target: row.get(1)?,
+                        action: row.get(2)?,
+                        success: row.get(3)?,
+                        detail: row.get(4)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            Ok(calls)
+        })
+    }
+
+    pub(crate) fn finish(
+        &self,
+        id: i64,
+        status: Status,
+        error: Option<String>,
+    ) -> anyhow::Result<()> {
+        blocking(|| {
+            let conn = self.conn()?;
+            conn.execute(
+                "UPDATE history SET status = ?1, finished_at = ?2, error = ?3 WHERE id = ?4",
+                params![status.as_str(), now(), error, id],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    /// Mark the entry `id` as cancelled, unless it already finished. Returns whether it was
+    /// still running.
+    pub(crate) fn cancel(&self, id: i64, error: &str) -> anyhow::Result<bool> {
+        blocking(|| {
+            let conn = self.conn()?;
+            let cancelled = conn.execute(
+                "UPDATE history SET status = ?1, finished_at = ?2, error = ?3 \
+                 WHERE id = ?4 AND status = ?5",
+                params![
+                    Status::Cancelled.as_str(),
+                    now(),
+                    error,
+                    id,
+                    Status::Running.as_str()
+                ],
+            )?;
+
+            Ok(cancelled > 0)
+        })
+    }
+}
+
+/// Handle to the history entry of the request currently being processed.
+#[derive(Clone)]
+pub(crate) struct Record {
+    /// The database and the id of the entry, `None` if the request could not be stored
+    entry: Option<(Arc<Db>, i64)>,
+    dry_run: bool,
+}
+
+impl Record {
+    /// Record of a request without a history entry, handled although storing it failed. Nothing
+    /// about it is stored.
+    pub(crate) fn unrecorded(origin: &Origin) -> Self {
+        Record {
+            entry: None,
+            dry_run: origin.dry_run,
+        }
+    }
+
+    pub(crate) fn id(&self) -> Option<i64> {
+        self.entry.as_ref().map(|(_, id)| *id)
+    }
+
+    pub(crate) fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Whether the outbound call `action` on `target` has to be skipped because this is a dry
+    /// run. The caller records the call with a simulated response instead.
+    pub(crate) fn skips(&self, target: &str, action: &str) -> bool {
+        if self.dry_run {
+            info!("Dry run, skipping {action} on {target}.");
+        }
+
+        self.dry_run
+    }
+
+    /// Outbound calls stored so far.
+    pub(crate) fn calls(&self) -> anyhow::Result<Vec<OutboundCall>> {
+        match &self.entry {
+            Some((db, id)) => db.calls(*id),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Store an outbound call made while handling the request. Failures are only logged since
+    /// they must not abort the request itself.
+    pub(crate) fn call(&self, target: &str, action: &str, success: bool, detail: impl ToString) {
+        let Some((db, id)) = &self.entry else {
+            return;
+        };
+        let detail = match self.dry_run {
+            true => format!("dry run: {}", detail.to_string()),
+            false => detail.to_string(),
+        };
+        let result = blocking(|| {
+            let conn = db.conn()?;
+            conn.execute(
+                "INSERT INTO history_calls (history_id, created_at, target, action, success, \
+                 detail) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![id, now(), target, action, success, detail],
+            )?;
+            anyhow::Ok(())
+        });
+
+        if let Err(err) = result {
+            warn!("Could not store outbound call in history: {err}");
+        }
+    }
+
+    /// Store an outbound HTTP call, treating any 2xx status code as success.
+    pub(crate) fn call_status(
+        &self,
+        target: &str,
+        action: &str,
+        result: &anyhow::Result<StatusCode>,
+    ) {
+        match result {
+            Ok(status_code) => self.call(target, action, status_code.is_success(), status_code),
+            Err(err) => self.call(target, action, false, err),
+        }
+    }
+
+    pub(crate) fn finish<T>(&self, result: &anyhow::Result<T>) {
+        match result {
+            Ok(_) => self.finish_with(Status::Success, None),
+            Err(err) => self.finish_with(Status::Failed, Some(err.to_string())),
+        }
+    }
+
+    pub(crate) fn finish_with(&self, status: Status, error: Option<String>) {
+        let Some((db, id)) = &self.entry else {
+            return;
+        };
+        if let Err(err) = db.finish(*id, status, error) {
+            warn!("Could not update history entry {id}: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(db: &Arc<Db>, endpoint: &str, title: &str, status: Status) -> i64 {
+        let fields = EntryFields {
+            release_title: Some(title.to_string()),
+            download_id: Some(format!("{title}-hash")),
+            ..Default::default()
+        };
+        let record = db.start(endpoint, &Origin::default(), fields, &()).unwrap();
+        record.finish_with(status, None);
+        record.id().unwrap()
+    }
+
+    fn ids(db: &Db, filter: HistoryFilter) -> Vec<i64> {
+        db.list(&filter)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    #[test]
+    fn list_filters() {
+        let db = Arc::new(Db::open(":memory:").unwrap());
+        let sonarr = entry(&db, "/xseed-sonarr", "Show.S01E01", Status::Success);
+        let radarr = entry(&db, "/xseed-radarr", "Movie.2024", Status::Failed);
+        let announce = entry(&db, "/announce", "Show.S01E02", Status::Success);
+
+        assert_eq!(
+            ids(&db, HistoryFilter::default()),
+            [announce, radarr, sonarr]
+        );
+
+        let endpoints = HistoryFilter {
+            endpoint: Some("/xseed-sonarr, /xseed-radarr".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, endpoints), [radarr, sonarr]);
+
+        let single = HistoryFilter {
+            endpoint: Some("/announce".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, single), [announce]);
+
+        let unknown = HistoryFilter {
+            endpoint: Some("/xseed-sonarr' OR 1=1 --".to_string()),
+            ..Default::default()
+        };
+        assert!(ids(&db, unknown).is_empty());
+
+        let title = HistoryFilter {
+            release_title: Some("Show.S01".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, title), [announce, sonarr]);
+
+        let failed = HistoryFilter {
+            status: Some(Status::Failed),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, failed), [radarr]);
+
+        let download_id = HistoryFilter {
+            download_id: Some("Movie.2024-hash".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, download_id), [radarr]);
+
+        let combined = HistoryFilter {
+            endpoint: Some("/xseed-sonarr,/announce".to_string()),
+            status: Some(Status::Success),
+            release_title: Some("S01E02".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, combined), [announce]);
+
+        let limited = HistoryFilter {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ids(&db, limited), [announce]);
+
+        let future = HistoryFilter {
+            since: Some(now() + 60),
+            ..Default::default()
+        };
+        assert!(ids(&db, future).is_empty());
+
+        let past = HistoryFilter {
+            until: Some(now() - 60),
+            ..Default::default()
+        };
+        assert!(ids(&db, past).is_empty());
+    }
+
+    #[test]
+    fn counts_failed_replays() {
+        let db = Arc::new(Db::open(":memory:").unwrap());
+        let original = entry(&db, "/xseed-sonarr", "Show.S01E01", Status::Failed);
+
+        let replay = |status: Status, dry_run: bool| {
+            let fields = EntryFields {
+                replay_of: Some(original),
+                ..Default::default()
+            };
+            let origin = Origin {
+                dry_run,
+                ..Default::default()
+            };
+            let record = db.start("/xseed-sonarr", &origin, fields, &()).unwrap();
+            record.finish_with(status, None);
+        };
+
+        replay(Status::Failed, false);
+        replay(Status::Failed, true);
+        assert_eq!(db.failed_replays(original).unwrap(), 1);
+        assert!(!db.replayed_successfully(original).unwrap());
+
+        replay(Status::Failed, false);
+        replay(Status::Success, false);
+        assert_eq!(db.failed_replays(original).unwrap(), 2);
+        assert!(db.replayed_successfully(original).unwrap());
+    }
+
+    #[test]
+    fn cancels_only_running_entries() {
+        let db = Arc::new(Db::open(":memory:").unwrap());
+        let running = db
+            .start(
+                "/xseed-sonarr",
+                &Origin::default(),
+                EntryFields::default(),
+                &(),
+            )
+            .unwrap()
+            .id()
+            .unwrap();
+        let finished = entry(&db, "/xseed-sonarr", "Show.S01E01", Status::Success);
+
+        assert!(db.cancel(running, "Cancelled by user.").unwrap());
+        assert!(!db.cancel(finished, "Cancelled by user.").unwrap());
+
+        let running = db.get(running).unwrap().unwrap();
+        assert_eq!(running.status, Status::Cancelled);
+        assert_eq!(running.error.as_deref(), Some("Cancelled by user."));
+        let finished = db.get(finished).unwrap().unwrap();
+        assert_eq!(finished.status, Status::Success);
+        assert_eq!(finished.error, None);
+    }
+
+    #[test]
+    fn migrates_from_v1() {
+        let path = std::env::temp_dir().join(format!("history-v1-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(SCHEMA_V1).unwrap();
+            conn.pragma_update(None, "user_version", 1).unwrap();
+            conn.execute(
+                "INSERT INTO history (endpoint, received_at, status, release_title, payload) \
+                 VALUES ('/xseed-sonarr', 1, 'failed', 'Show.S01E01', '{}')",
+                [],
+            )
+            .unwrap();
+        }
+
+        let db = Arc::new(Db::open(&path).unwrap());
+        let version: usize = db
+            .conn()
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let old = db.get(1).unwrap().unwrap();
+        assert_eq!(old.release_title.as_deref(), Some("Show.S01E01"));
+        assert_eq!(old.replay_of, None);
+        assert_eq!(old.api_key, None);
+        assert!(!old.dry_run);
+
+        // the new columns are usable
+        let fields = EntryFields {
+            replay_of: Some(1),
+            ..Default::default()
+        };
+        let origin = Origin {
+            api_key: Some("sonarr".to_string()),
+            dry_run: true,
+            ..Default::default()
+        };
+        let record = db.start("/xseed-sonarr", &origin, fields, &()).unwrap();
+        let new = db.get(record.id().unwrap()).unwrap().unwrap();
+        assert_eq!(new.replay_of, Some(1));
+        assert_eq!(new.api_key.as_deref(), Some("sonarr"));
+        assert!(new.dry_run);
+        drop(db);
+
+        // opening again doesn't apply any migration twice
+        Db::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,220 @@
-use serenity::builder::ExecuteWebhook;
-use serenity::http::Http;
-use serenity::model::webhook::Webhook;
+use std::time::Duration;
 
-pub(crate) async fn discord_webhook(
-    webhook_url: &str,
-    content: &str,
-) -> anyhow::Result<()> {
-    let http = Http::new("");
-    let webhook = Webhook::from_url(&http, webhook_url).await?;
+use serde::Deserialize;
 
-    let builder = ExecuteWebhook::new().content(content).username("cross-seed-tools");
-    webhook.execute(&http, false, builder).await.expect("Could not execute webhook.");
+use reqwest::{Client, StatusCode};
+
+use serenity::builder::{CreateEmbed, CreateEmbedFooter, ExecuteWebhook};
+use serenity::model::{Colour, Timestamp};
+
+use crate::notify::{Event, RetryAfter};
+
+const USERNAME: &str = "cross-seed-tools";
+
+/// Body of a `429` response.
+#[derive(Deserialize)]
+struct RateLimited {
+    /// Seconds
+    retry_after: f64,
+}
+
+/// Green if everything worked, orange if the release was handled after some calls failed,
+/// red if it failed.
+fn colour(event: &Event) -> Colour {
+    if !event.kind.success() {
+        Colour::RED
+    } else if event.targets.iter().any(|call| !call.success) {
+        Colour::ORANGE
+    } else {
+        Colour::DARK_GREEN
+    }
+}
+
+// Discord rejects embeds exceeding these limits (in characters) with a 400
+const TITLE_LIMIT: usize = 256;
+const DESCRIPTION_LIMIT: usize = 4096;
+const FIELD_VALUE_LIMIT: usize = 1024;
+const EMBED_LIMIT: usize = 6000;
+/// Limit of the short release details, keeping room for the description within `EMBED_LIMIT`
+const DETAIL_LIMIT: usize = 256;
+
+/// `text` cut to at most `limit` characters, ending with an ellipsis if it was cut.
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+
+    let mut truncated = text
+        .chars()
+        .take(limit.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+fn to_embed(event: &Event) -> CreateEmbed {
+    let release = &event.release;
+
+    let title = truncate(event.headline(), TITLE_LIMIT);
+    let mut embed = CreateEmbed::new()
+        .title(&title)
+        .colour(colour(event))
+        .footer(CreateEmbedFooter::new(USERNAME))
+        .timestamp(Timestamp::now());
+
+    if let Some(poster) = &release.poster {
+        embed = embed.thumbnail(poster);
+    }
+    if let Some(link) = &release.link {
+        embed = embed.url(link);
+    }
+
+    let mut fields = Vec::new();
+    // a template replaces all details
+    let description = match &event.custom_message {
+        Some(message) => Some(message),
+        None => {
+            let details = [
+                ("Indexer", &release.indexer),
+                ("Release type", &release.release_type),
+                ("Client", &release.client),
+            ];
+            for (name, value) in details {
+                if let Some(value) = value.as_ref().filter(|v| !v.is_empty()) {
+                    fields.push((name, truncate(value, DETAIL_LIMIT), true));
+                }
+            }
+            if let Some(download_id) = &release.download_id {
+                let download_id = truncate(download_id, DETAIL_LIMIT - 2);
+                fields.push(("Download ID", format!("`{download_id}`"), false));
+            }
+
+            if !event.targets.is_empty() {
+                let targets = event
+                    .targets
+                    .iter()
+                    .map(|call| {
+                        format!(
+                            "{} {} {}: {}",
+                            if call.success { "✅" } else { "❌" },
+                            call.target,
+                            call.action,
+                            call.detail.as_deref().unwrap_or_default()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                fields.push(("Targets", truncate(&targets, FIELD_VALUE_LIMIT), false));
+            }
+
+            if let Some(error) = &event.error {
+                fields.push(("Error", truncate(error, FIELD_VALUE_LIMIT), false));
+            }
+
+            release.release_title.as_ref()
+        }
+    };
+
+    // the description gets what is left of the limit of the whole embed
+    let used = title.chars().count()
+        + USERNAME.len()
+        + fields
+            .iter()
+            .map(|(name, value, _)| name.len() + value.chars().count())
+            .sum::<usize>();
+    if let Some(description) = description {
+        let limit = DESCRIPTION_LIMIT.min(EMBED_LIMIT.saturating_sub(used));
+        embed = embed.description(truncate(description, limit));
+    }
+
+    embed.fields(fields)
+}
+
+/// Execute the webhook. Rate limits are returned as `RetryAfter` with the time Discord asks
+/// to wait.
+pub(crate) async fn discord_embed(webhook_url: &str, event: &Event) -> anyhow::Result<()> {
+    let builder = ExecuteWebhook::new()
+        .embed(to_embed(event))
+        .username(USERNAME);
+
+    let response = Client::new()
+        .post(webhook_url)
+        .json(&builder)
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        let rate_limited = response.json::<RateLimited>().await?;
+        return Err(RetryAfter(Duration::from_secs_f64(rate_limited.retry_after.max(0.0))).into());
+    }
+    response.error_for_status()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::OutboundCall;
+    use crate::notify::{EventKind, Release};
+
+    fn length(value: &serde_json::Value) -> usize {
+        value.as_str().map_or(0, |text| text.chars().count())
+    }
+
+    #[test]
+    fn truncates_on_char_boundaries() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly", 7), "exactly");
+        assert_eq!(truncate("ääääää", 4), "äää…");
+        assert_eq!(truncate("❌❌❌", 2), "❌…");
+    }
+
+    #[test]
+    fn oversized_event_fits_into_embed() {
+        let release = Release {
+            release_title: Some("R".repeat(10_000)),
+            indexer: Some("I".repeat(2000)),
+            client: Some("C".repeat(2000)),
+            download_id: Some("D".repeat(2000)),
+            ..Default::default()
+        };
+        let mut event = Event::new(EventKind::XseedFailed, release);
+        event.error = Some("é".repeat(5000));
+        event.targets = (0..100)
+            .map(|i| OutboundCall {
+                created_at: 0,
+                target: format!("cross-seed-{i}"),
+                action: "webhook (path)".to_string(),
+                success: false,
+                detail: Some("x".repeat(100)),
+            })
+            .collect();
+
+        let embed = serde_json::to_value(to_embed(&event)).unwrap();
+
+        let description = length(&embed["description"]);
+        assert!(description <= DESCRIPTION_LIMIT);
+        assert!(embed["description"].as_str().unwrap().ends_with('…'));
+        assert!(length(&embed["title"]) <= TITLE_LIMIT);
+
+        let fields = embed["fields"].as_array().unwrap();
+        let mut total = length(&embed["title"]) + description + USERNAME.len();
+        for field in fields {
+            assert!(length(&field["value"]) <= FIELD_VALUE_LIMIT);
+            total += length(&field["name"]) + length(&field["value"]);
+        }
+        assert!(total <= EMBED_LIMIT, "embed has {total} characters");
+        assert!(fields.iter().any(|field| field["name"] == "Error"));
+    }
+
+    #[test]
+    fn oversized_custom_message() {
+        let mut event = Event::new(EventKind::XseedFailed, Release::default());
+        event.custom_message = Some("m".repeat(5000));
+
+        let embed = serde_json::to_value(to_embed(&event)).unwrap();
+        assert_eq!(length(&embed["description"]), DESCRIPTION_LIMIT);
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use anyhow::{anyhow, bail};
+
+use qbit_rs::{model::Credential, Qbit};
+
+use crate::cross_seed::cross_seed_ping;
+use crate::tls::TlsFiles;
+use crate::AppState;
+
+/// Result of checking whether an integration is reachable.
+pub(crate) struct Health {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+async fn check_cross_seed(name: &'static str, url: Option<String>) -> Option<Health> {
+    let url = url?;
+    let (ok, detail) = match cross_seed_ping(&url).await {
+        Ok(status_code) => (status_code.is_success(), status_code.to_string()),
+        Err(err) => (false, err.to_string()),
+    };
+
+    Some(Health { name, ok, detail })
+}
+
+async fn check_qbittorrent(
+    name: &'static str,
+    host: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+) -> Option<Health> {
+    let host = host?;
+    let result = async {
+        let host = reqwest::Url::parse(&host)?;
+        let qbit = Qbit::new(
+            host,
+            Credential::new(user.unwrap_or_default(), password.unwrap_or_default()),
+        );
+        let version = tokio::time::timeout(Duration::from_secs(5), qbit.get_version())
+            .await
+            .map_err(|_| anyhow!("Timed out"))??;
+
+        anyhow::Ok(version)
+    }
+    .await;
+
+    let (ok, detail) = match result {
+        Ok(version) => (true, format!("qBittorrent {version}")),
+        Err(err) => (false, err.to_string()),
+    };
+
+    Some(Health { name, ok, detail })
+}
+
+/// Check all configured integrations concurrently.
+pub(crate) async fn integration_health(
+    state: &Arc<RwLock<AppState>>,
+) -> anyhow::Result<Vec<Health>> {
+    let state = state
+        .read()
+        .map_err(|_| anyhow!("Could not read from state."))?
+        .clone();
+
+    let (cross_seed_local, cross_seed_seedbox, qbittorrent_local, qbittorrent_seedbox) = tokio::join!(
+        check_cross_seed("cross-seed-local", state.cross_seed_local_url),
+        check_cross_seed("cross-seed-seedbox", state.cross_seed_seedbox_url),
+        check_qbittorrent(
+            "qbittorrent-local",
+            state.qbittorrent_local_host,
+            state.qbittorrent_local_user,
+            state.qbittorrent_local_password,
+        ),
+        check_qbittorrent(
+            "qbittorrent-seedbox",
+            state.qbittorrent_seedbox_host,
+            state.qbittorrent_seedbox_user,
+            state.qbittorrent_seedbox_password,
+        ),
+    );
+
+    let mut health = vec![
+        cross_seed_local,
+        cross_seed_seedbox,
+        qbittorrent_local,
+        qbittorrent_seedbox,
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    if !state.notification_targets.is_empty() {
+        let targets = state
+            .notification_targets
+            .iter()
+            .map(|target| format!("{} ({})", target.name, target.kind()))
+            .collect::<Vec<_>>();
+
+        health.push(Health {
+            name: "notifications",
+            ok: true,
+            detail: format!("configured: {}", targets.join(", ")),
+        });
+    }
+
+    if !state.arr_instances.is_empty() {
+        let mut instances = state.arr_instances.keys().cloned().collect::<Vec<_>>();
+        instances.sort();
+
+        health.push(Health {
+            name: "arr-instances",
+            ok: true,
+            detail: format!("configured: {}", instances.join(", ")),
+        });
+    }
+
+    Ok(health)
+}
+
+/// Check the configuration from the command line. Parsing the configuration already happened
+/// when creating the state, here the TLS files are loaded and all integrations are contacted.
+pub(crate) async fn check_config_command(state: Arc<RwLock<AppState>>) -> anyhow::Result<()> {
+    if let Some(files) = TlsFiles::from_env()? {
+        files.validate()?;
+        info!("TLS certificate: ok");
+    }
+
+    let mut any_failed = false;
+
+    for health in integration_health(&state).await? {
+        if health.ok {
+            info!("{}: ok ({})", health.name, health.detail);
+        } else {
+            error!("{}: error ({})", health.name, health.detail);
+            any_failed = true;
+        }
+    }
+
+    if any_failed {
+        bail!("Not all integrations are reachable.");
+    }
+
+    info!("Configuration is valid.");
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tracing::{error, field::Empty, info, info_span, warn, Instrument};
+
+use anyhow::{anyhow, bail, Context};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use serde::Serialize;
+
+use crate::data_types::radarr::RadarrConnectWebhook;
+use crate::data_types::sonarr::SonarrConnectWebhook;
+use crate::data_types::ArrImport;
+use crate::db::{now, Db, EntryFields, HistoryEntry, HistoryFilter, Origin, Record, Status};
+use crate::inject_seedbox_torrents::inject_seedbox_torrents;
+use crate::jobs::run_recorded_job;
+use crate::notify::{notify, Event, EventKind, Release};
+use crate::xseed::xseed;
+use crate::AppState;
+
+/// Endpoints whose requests can be replayed from the history.
+pub(crate) const REPLAYABLE_ENDPOINTS: &[&str] = &[
+    "/xseed-sonarr",
+    "/xseed-radarr",
+    "/inject-seedbox-torrents-sonarr",
+    "/inject-seedbox-torrents-radarr",
+];
+
+pub(crate) fn db(state: &Arc<RwLock<AppState>>) -> anyhow::Result<Arc<Db>> {
+    let read_guard = state
+        .read()
+        .map_err(|_| anyhow!("Could not read from state."))?;
+
+    Ok(read_guard.db.clone())
+}
+
+/// Store the incoming request sent to `endpoint`. The history must not keep requests from being
+/// handled, if storing it fails the request is handled without a history entry.
+pub(crate) fn record_request(
+    state: &Arc<RwLock<AppState>>,
+    endpoint: &str,
+    origin: &Origin,
+    fields: EntryFields,
+    payload: &impl Serialize,
+) -> Record {
+    match db(state).and_then(|db| db.start(endpoint, origin, fields, payload)) {
+        Ok(record) => record,
+        Err(err) => {
+            error!("Could not store request in history, handling it without: {err}");
+            Record::unrecorded(origin)
+        }
+    }
+}
+
+/// Response to a successfully handled request. For dry runs it lists the outbound calls which
+/// would have been made.
+pub(crate) fn finished_response(record: &Record) -> Response {
+    if !record.dry_run() {
+        return StatusCode::OK.into_response();
+    }
+
+    match record.calls() {
+        Ok(calls) => Json(calls).into_response(),
+        Err(err) => {
+            error!("Error occured: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Failed replays of a request before the `replay` command gives up on it, see
+/// `REPLAY_MAX_ATTEMPTS`.
+fn max_attempts(state: &Arc<RwLock<AppState>>) -> anyhow::Result<u32> {
+    let read_guard = state
+        .read()
+        .map_err(|_| anyhow!("Could not read from state."))?;
+
+    Ok(read_guard.replay_max_attempts)
+}
+
+/// Run the request stored in `entry` again with its original payload. The outcome is stored as
+/// a new history entry pointing back to the original one.
+pub(crate) async fn replay(
+    state: Arc<RwLock<AppState>>,
+    entry: HistoryEntry,
+    origin: &Origin,
+) -> anyhow::Result<HistoryEntry> {
+    let db = db(&state)?;
+    let original = entry.replay_of.unwrap_or(entry.id);
+
+    let fields = EntryFields {
+        release_title: entry.release_title.clone(),
+        download_id: entry.download_id.clone(),
+        download_client: entry.download_client.clone(),
+        // replays always point to the original request
+        replay_of: Some(original),
+    };
+
+    let span = info_span!(
+        "replay",
+        replay_of = entry.id,
+        release_title = Empty,
+        download_id = Empty,
+        client = Empty,
+    );
+
+    let record = db.start(&entry.endpoint, origin, fields, &entry.payload)?;
+
+    let replayed_id = entry.id;
+    let id = record.id().context("Replay without a history entry.")?;
+    let release = Release {
+        release_title: entry.release_title.clone(),
+        client: entry.download_client.clone(),
+        download_id: entry.download_id.clone(),
+        ..Default::default()
+    };
+    let job = {
+        let state = state.clone();
+        |record: Record| {
+            async move {
+                match entry.endpoint.as_str() {
+                    "/xseed-sonarr" => {
+                        let payload: SonarrConnectWebhook = serde_json::from_value(entry.payload)?;
+                        xseed(ArrImport::from(&payload), state, &record).await
+                    }
+                    "/xseed-radarr" => {
+                        let payload: RadarrConnectWebhook = serde_json::from_value(entry.payload)?;
+                        xseed(ArrImport::from(&payload), state, &record).await
+                    }
+                    "/inject-seedbox-torrents-sonarr" => {
+                        let payload: SonarrConnectWebhook = serde_json::from_value(entry.payload)?;
+                        inject_seedbox_torrents(ArrImport::from(&payload), state, &record).await
+                    }
+                    "/inject-seedbox-torrents-radarr" => {
+                        let payload: RadarrConnectWebhook = serde_json::from_value(entry.payload)?;
+                        inject_seedbox_torrents(ArrImport::from(&payload), state, &record).await
+                    }
+                    endpoint => Err(anyhow!("Requests to {endpoint} can not be replayed.")),
+                }
+            }
+            .instrument(span)
+        }
+    };
+    let result = run_recorded_job(&state, record.clone(), job).await;
+
+    // each failed attempt is notified about by the request itself, only using up all attempts is
+    // worth a notification of its own
+    if let Err(err) = &result {
+        warn!("Replay of history entry {replayed_id} failed: {err}");
+
+        if !record.dry_run() && db.failed_replays(original)? == max_attempts(&state)? {
+            let event = Event::new(EventKind::RetryExhausted, release).with_error(err);
+            notify(&state, &record, event);
+        }
+    }
+
+    db.get(id)?
+        .context("History entry of the replay not found.")
+}
+
+/// Entries to replay: the replayable requests received in the last `since`, optionally only the
+/// failed ones, oldest first. A request and its earlier replays are only replayed once, otherwise
+/// every run would replay each failed replay again. Dry runs are never replayed, replays make
+/// their outbound calls for real.
+fn replay_candidates(db: &Db, failed: bool, since: Duration) -> anyhow::Result<Vec<HistoryEntry>> {
+    let filter = HistoryFilter {
+        status: failed.then_some(Status::Failed),
+        since: Some(now() - since.as_secs() as i64),
+        limit: Some(u32::MAX),
+        ..Default::default()
+    };
+
+    let mut entries = db
+        .list(&filter)?
+        .into_iter()
+        .filter(|entry| !entry.dry_run)
+        .filter(|entry| REPLAYABLE_ENDPOINTS.contains(&entry.endpoint.as_str()))
+        .collect::<Vec<_>>();
+    // oldest first
+    entries.reverse();
+
+    let mut originals = HashSet::new();
+    entries.retain(|entry| originals.insert(entry.replay_of.unwrap_or(entry.id)));
+
+    Ok(entries)
+}
+
+/// Replay all stored xseed / inject requests received in the last `since`, optionally only the
+/// failed ones. Entries which were already replayed successfully or `REPLAY_MAX_ATTEMPTS` times
+/// are skipped.
+pub(crate) async fn replay_command(
+    state: Arc<RwLock<AppState>>,
+    failed: bool,
+    since: Duration,
+) -> anyhow::Result<()> {
+    let db = db(&state)?;
+
+    let max_attempts = max_attempts(&state)?;
+    let mut any_failed = false;
+
+    for entry in replay_candidates(&db, failed, since)? {
+        let original = entry.replay_of.unwrap_or(entry.id);
+        if db.replayed_successfully(original)? {
+            info!(
+                "History entry {} was already replayed successfully.",
+                entry.id
+            );
+            continue;
+        }
+        if db.failed_replays(original)? >= max_attempts {
+            warn!("History entry {original} was already replayed {max_attempts} times.");
+            any_failed = true;
+            continue;
+        }
+
+        let id = entry.id;
+        let title = entry.release_title.clone().unwrap_or_default();
+        let replayed = replay(state.clone(), entry, &Origin::command_line(&state)).await?;
+
+        info!(
+            "Replayed history entry {id} ({title}) as {}: {:?}",
+            replayed.id, replayed.status
+        );
+        any_failed |= replayed.status != Status::Success;
+    }
+
+    if any_failed {
+        bail!("Not all replays succeeded.");
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn history_list(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Query(filter): Query<HistoryFilter>,
+) -> Result<Json<Vec<HistoryEntry>>, StatusCode> {
+    match db(&state).and_then(|db| db.list(&filter)) {
+        Ok(entries) => Ok(Json(entries)),
+        Err(err) => {
+            error!("Error occured: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub(crate) async fn history_get(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<i64>,
+) -> Result<Json<HistoryEntry>, StatusCode> {
+    match db(&state).and_then(|db| db.get(id)) {
+        Ok(Some(entry)) => Ok(Json(entry)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("Error occured: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub(crate) async fn history_replay(
+    State(state): State<Arc<RwLock<AppState>>>,
+    origin: Origin,
+    Path(id): Path<i64>,
+) -> Result<Json<HistoryEntry>, StatusCode> {
+    let entry = match db(&state).and_then(|db| db.get(id)) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("Error occured: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if !REPLAYABLE_ENDPOINTS.contains(&entry.endpoint.as_str()) {
+        warn!("Requests to {} can not be replayed.", entry.endpoint);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match replay(state, entry, &origin).await {
+        Ok(entry) => Ok(Json(entry)),
+        Err(err) => {
+            error!("Error occured: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed_with(db: &Arc<Db>, endpoint: &str, replay_of: Option<i64>, dry_run: bool) -> i64 {
+        let fields = EntryFields {
+            replay_of,
+            ..Default::default()
+        };
+        let origin = Origin {
+            dry_run,
+            ..Default::default()
+        };
+        let record = db
+            .start(endpoint, &origin, fields, &serde_json::json!({}))
+            .unwrap();
+        record.finish_with(Status::Failed, Some("failed".to_string()));
+        record.id().unwrap()
+    }
+
+    fn failed(db: &Arc<Db>, endpoint: &str, replay_of: Option<i64>) -> i64 {
+        failed_with(db, endpoint, replay_of, false)
+    }
+
+    #[test]
+    fn replays_each_failed_request_once() {
+        let db = Arc::new(Db::open(":memory:").unwrap());
+        let since = Duration::from_secs(60 * 60);
+
+        let original = failed(&db, "/xseed-sonarr", None);
+        failed(&db, "/announce", None);
+
+        // first run
+        let candidates = replay_candidates(&db, true, since).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, original);
+        failed(&db, "/xseed-sonarr", Some(original));
+
+        // second run, the failed replay must not be replayed as well
+        let candidates = replay_candidates(&db, true, since).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, original);
+        failed(&db, "/xseed-sonarr", Some(original));
+
+        let candidates = replay_candidates(&db, true, since).unwrap();
+        assert_eq!(candidates.len(), 1);
+    }
+
+    #[test]
+    fn never_replays_dry_runs() {
+        let db = Arc::new(Db::open(":memory:").unwrap());
+        let since = Duration::from_secs(60 * 60);
+
+        failed_with(&db, "/xseed-sonarr", None, true);
+        assert!(replay_candidates(&db, true, since).unwrap().is_empty());
+        assert!(replay_candidates(&db, false, since).unwrap().is_empty());
+
+        // a failed dry run replay of a real request doesn't stand in for it
+        let original = failed(&db, "/inject-seedbox-torrents-radarr", None);
+        failed_with(&db, "/inject-seedbox-torrents-radarr", Some(original), true);
+        let candidates = replay_candidates(&db, true, since).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, original);
+        assert!(!candidates[0].dry_run);
+    }
+
+    #[test]
+    fn replays_failed_replay_of_successful_request() {
+        let db = Arc::new(Db::open(":memory:").unwrap());
+        let since = Duration::from_secs(60 * 60);
+
+        let record = db
+            .start(
+                "/xseed-radarr",
+                &Origin::default(),
+                EntryFields::default(),
+                &(),
+            )
+            .unwrap();
+        record.finish_with(Status::Success, None);
+        let replay = failed(&db, "/xseed-radarr", record.id());
+        failed(&db, "/xseed-radarr", record.id());
+
+        let candidates = replay_candidates(&db, true, since).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, replay);
+    }
+}
@@ -1,29 +1,37 @@
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 
-use log::{error, info, trace};
+use tracing::{error, info, trace, warn, Span};
 
 use anyhow::{anyhow, Context};
 
 use tokio::time::{sleep, Duration};
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{self, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
 
 use qbit_rs::{
-    model::{AddTorrentArg, Credential, TorrentFile, TorrentSource},
+    model::{AddTorrentArg, Credential, GetTorrentListArg, TorrentFile, TorrentSource},
     Qbit,
 };
 
-use crate::cross_seed::{cross_seed_webhook, WebhookRequest};
+use crate::arr_events::{files_deleted, grabbed, upgraded, with_grab};
+use crate::arr_instances::{arr_instance, route_instance};
+use crate::cross_seed::WebhookRequest;
+use crate::db::{EntryFields, Origin, Record};
+use crate::history::{db, finished_response, record_request};
+use crate::jobs::run_recorded_job;
+use crate::notify::{notify, Event, EventKind, Release};
+use crate::xseed::send_webhook;
 use crate::AppState;
 
 use crate::data_types::radarr::RadarrConnectWebhook;
 use crate::data_types::sonarr::SonarrConnectWebhook;
-
-enum ArrConnectWebhook {
-    Sonarr(SonarrConnectWebhook),
-    Radarr(RadarrConnectWebhook),
-}
+use crate::data_types::{ArrEvent, ArrImport};
 
 fn get_category(sourcepath: &Path, local_qbit_dir: &Path) -> String {
     sourcepath
@@ -37,61 +45,143 @@ fn get_category(sourcepath: &Path, local_qbit_dir: &Path) -> String {
         .to_string()
 }
 
-async fn inject_seedbox_torrents(
-    request: ArrConnectWebhook,
+/// Notify about the outcome of an injection. `result` holds whether the data of the torrent was
+/// complete in the local qbittorrent.
+fn notify_inject(
+    state: &Arc<RwLock<AppState>>,
+    record: &Record,
+    release: Release,
+    result: &anyhow::Result<bool>,
+) {
+    let event = match result {
+        Ok(true) => Event::new(EventKind::InjectOk, release),
+        Ok(false) => Event::new(EventKind::InjectWaitingForData, release),
+        Err(err) => Event::new(EventKind::InjectFailed, release).with_error(err),
+    };
+    notify(state, record, event);
+}
+
+/// Handle a Sonarr / Radarr event: imports from the seedbox are injected, everything else is only
+/// handled if enabled, see `ArrEventOptions`.
+pub(crate) async fn inject_seedbox_torrents(
+    request: ArrImport,
     state: Arc<RwLock<AppState>>,
+    record: &Record,
 ) -> anyhow::Result<()> {
-    let event_type = match &request {
-        ArrConnectWebhook::Sonarr(request) => &request.event_type,
-        ArrConnectWebhook::Radarr(request) => &request.event_type,
-    };
+    match request.event {
+        ArrEvent::Test => {
+            info!("Test event detected.");
+            Ok(())
+        }
+        ArrEvent::Grab => grabbed(&state, Release::from(&request)),
+        ArrEvent::Import => inject(&request, &state, record).await,
+        ArrEvent::Upgrade => {
+            let result = inject(&request, &state, record).await;
+            let instance = request.instance_name.as_deref();
+            // the import matters more, a failed cleanup is only logged
+            if let Err(err) = upgraded(&state, instance, &request.removed_files, record).await {
+                warn!("Could not clean up the files replaced by the upgrade: {err}");
+            }
+            result
+        }
+        ArrEvent::FileDelete => {
+            files_deleted(
+                &state,
+                request.instance_name.as_deref(),
+                &request.removed_files,
+                record,
+            )
+            .await
+        }
+        ArrEvent::Other => {
+            info!("{} event ignored.", request.event_type);
+            Ok(())
+        }
+    }
+}
+
+/// Inject the torrent of the import and notify about the outcome.
+async fn inject(
+    request: &ArrImport,
+    state: &Arc<RwLock<AppState>>,
+    record: &Record,
+) -> anyhow::Result<()> {
+    let result = inject_import(request, state, record).await.transpose();
 
-    if event_type == "Test" {
-        info!("[/inject-seedbox-torrents] Test event detected.");
-        return Ok(());
+    if let Some(result) = result {
+        let release = with_grab(state, Release::from(request));
+        notify_inject(state, record, release, &result);
+        result?;
     }
+    Ok(())
+}
 
-    let client_id = match &request {
-        ArrConnectWebhook::Sonarr(request) => request.download_client.clone(),
-        ArrConnectWebhook::Radarr(request) => request.download_client.clone(),
+/// Inject the torrent of an import of the seedbox qbittorrent. Returns `None` for imports of
+/// other clients, otherwise whether the data of the torrent is complete locally.
+async fn inject_import(
+    request: &ArrImport,
+    state: &Arc<RwLock<AppState>>,
+    record: &Record,
+) -> anyhow::Result<Option<bool>> {
+    if let Some(release_title) = &request.release_title {
+        Span::current().record("release_title", release_title);
     }
-    .context("Request does not include a download_id.")?;
-    trace!("[/inject-seedbox-torrents] client_id: {client_id}");
 
-    let qbittorrent_seedbox_name = {
-        let read_guard = state
-            .read()
-            .map_err(|_| anyhow!("Could not read from state."))?;
+    let client_id = request
+        .download_client
+        .clone()
+        .context("Request does not include a download_client.")?;
+    Span::current().record("client", &client_id);
+    trace!("client_id: {client_id}");
 
-        read_guard
-            .qbittorrent_seedbox_name
-            .clone()
-            .context("QBITORRENT_SEEDBOX_NAME is not set.")?
-    };
+    let instance = request.instance_name.as_deref();
+    let settings = arr_instance(state, instance)?;
+    let qbittorrent_seedbox_name = settings
+        .qbittorrent_seedbox_name
+        .context("QBITORRENT_SEEDBOX_NAME is not set.")?;
 
     if client_id != qbittorrent_seedbox_name {
-        trace!("[/inject-seedbox-torrents] Download using {client_id}, which is not the seedbox qbittorrent");
-        return Ok(());
+        trace!("Download using {client_id}, which is not the seedbox qbittorrent");
+        return Ok(None);
     }
 
-    let download_id = match &request {
-        ArrConnectWebhook::Sonarr(request) => request.download_id.clone(),
-        ArrConnectWebhook::Radarr(request) => request.download_id.clone(),
-    }
-    .context("Request does not include a download_client.")?;
-    trace!("[/inject-seedbox-torrents] download_id: {download_id}");
-    let source_path = match &request {
-        ArrConnectWebhook::Sonarr(request) => request.source_path.clone(),
-        ArrConnectWebhook::Radarr(request) => request
-            .movie_file
-            .as_ref()
-            .map(|movie_file| movie_file.source_path.clone()),
-    }
-    .context("Couldn't set source_path based on the request.")?;
-    trace!("[/inject-seedbox-torrents] source_path: {source_path}");
+    let download_id = request
+        .download_id
+        .clone()
+        .context("Request does not include a download_id.")?;
+    Span::current().record("download_id", &download_id);
+    trace!("download_id: {download_id}");
+    let source_path = request
+        .source_path
+        .clone()
+        .context("Couldn't set source_path based on the request.")?;
+    trace!("source_path: {source_path}");
+
+    let qbittorrent_local_dir = settings
+        .qbittorrent_local_dir
+        .context("QBITORRENT_LOCAL_DIR is not set.")?;
+    let local_qbit_dir = Path::new(&qbittorrent_local_dir);
+    let source_path = Path::new(&source_path);
+    trace!("source_path: {}", source_path.to_str().unwrap());
+    trace!("local_qbit_dir: {}", local_qbit_dir.to_str().unwrap());
+    let category = get_category(source_path, local_qbit_dir);
+
+    inject_torrent(state, instance, &download_id, Some(category), record)
+        .await
+        .map(Some)
+}
 
+/// Copy the torrent `download_id` from the seedbox into the local qbittorrent and let the local
+/// cross-seed know about it, both the ones of `instance`. Without a `category` the one of the
+/// seedbox torrent is used. Returns whether the data of the torrent is complete locally.
+async fn inject_torrent(
+    state: &Arc<RwLock<AppState>>,
+    instance: Option<&str>,
+    download_id: &str,
+    category: Option<String>,
+    record: &Record,
+) -> anyhow::Result<bool> {
     let (
-        qbittorrent_local_dir,
         qbittorrent_seedbox_host,
         qbittorrent_seedbox_user,
         qbittorrent_seedbox_password,
@@ -101,14 +191,11 @@ async fn inject_seedbox_torrents(
         cross_seed_url,
         cross_seed_api_key,
     ) = {
+        let settings = arr_instance(state, instance)?;
         let read_guard = state
             .read()
             .map_err(|_| anyhow!("Could not read from state."))?;
 
-        let qbittorrent_local_dir = read_guard
-            .qbittorrent_local_dir
-            .clone()
-            .context("QBITORRENT_LOCAL_DIR is not set.")?;
         let qbittorrent_seedbox_host = read_guard
             .qbittorrent_seedbox_host
             .clone()
@@ -122,24 +209,20 @@ async fn inject_seedbox_torrents(
             .clone()
             .context("QBITORRENT_SEEDBOX_PASSWORD is not set.")?;
 
-        let qbittorrent_local_host = read_guard
+        let qbittorrent_local_host = settings
             .qbittorrent_local_host
-            .clone()
             .context("QBITORRENT_LOCAL_HOST is not set.")?;
-        let qbittorrent_local_user = read_guard
+        let qbittorrent_local_user = settings
             .qbittorrent_local_user
-            .clone()
             .context("QBITORRENT_LOCAL_USER is not set.")?;
-        let qbittorrent_local_password = read_guard
+        let qbittorrent_local_password = settings
             .qbittorrent_local_password
-            .clone()
             .context("QBITORRENT_LOCAL_PASSWORD is not set.")?;
 
-        let cross_seed_url = read_guard.cross_seed_local_url.clone();
-        let cross_seed_api_key = read_guard.cross_seed_local_api_key.clone();
+        let cross_seed_url = settings.cross_seed_local_url;
+        let cross_seed_api_key = settings.cross_seed_local_api_key;
 
         (
-            qbittorrent_local_dir,
             qbittorrent_seedbox_host,
             qbittorrent_seedbox_user,
             qbittorrent_seedbox_password,
@@ -150,33 +233,45 @@ async fn inject_seedbox_torrents(
             cross_seed_api_key,
         )
     };
-    let local_qbit_dir = Path::new(&qbittorrent_local_dir);
-    let source_path = Path::new(&source_path);
-    trace!(
-        "[/inject-seedbox-torrents] source_path: {}",
-        source_path.to_str().unwrap()
-    );
-    trace!(
-        "[/inject-seedbox-torrents] local_qbit_dir: {}",
-        local_qbit_dir.to_str().unwrap()
-    );
-    let category = get_category(source_path, local_qbit_dir);
-    trace!("[/inject-seedbox-torrents] category: {category}");
 
     let qbittorrent_seedbox_host = reqwest::Url::parse(&qbittorrent_seedbox_host)?;
     let qbit_creds = Credential::new(qbittorrent_seedbox_user, qbittorrent_seedbox_password);
     let qbit_seedbox = Qbit::new(qbittorrent_seedbox_host, qbit_creds);
 
-    info!("[/inject-seedbox-torrents] start with exporting...");
-    let torrent = qbit_seedbox.export_torrent(&download_id).await?;
-    info!("[/inject-seedbox-torrents] exported torrent from qbittorrent-seedbox");
+    let category = match category {
+        Some(category) => category,
+        None => {
+            let arg = GetTorrentListArg {
+                hashes: Some(download_id.to_string()),
+                ..Default::default()
+            };
+            qbit_seedbox
+                .get_torrent_list(arg)
+                .await?
+                .into_iter()
+                .next()
+                .with_context(|| format!("Torrent {download_id} not found on the seedbox."))?
+                .category
+                .unwrap_or_default()
+        }
+    };
+    trace!("category: {category}");
+
+    info!("start with exporting...");
+    let torrent = qbit_seedbox.export_torrent(download_id).await;
+    match &torrent {
+        Ok(_) => record.call("qbittorrent-seedbox", "export torrent", true, download_id),
+        Err(err) => record.call("qbittorrent-seedbox", "export torrent", false, err),
+    }
+    let torrent = torrent?;
+    info!("exported torrent from qbittorrent-seedbox");
 
     let local_qbit_host = reqwest::Url::parse(&qbittorrent_local_host)?;
     let qbit_creds = Credential::new(qbittorrent_local_user, qbittorrent_local_password);
     let qbit_local = Qbit::new(local_qbit_host, qbit_creds);
 
     let torrent_file = TorrentFile {
-        filename: download_id.clone(),
+        filename: download_id.to_string(),
         data: torrent.into(),
     };
     let add_torrent_arg = AddTorrentArg {
@@ -187,36 +282,119 @@ async fn inject_seedbox_torrents(
         auto_torrent_management: Some(true),
         ..Default::default()
     };
-    trace!(
-        "[/inject-seedbox-torrents] add_torrent_arg: {:?}",
-        add_torrent_arg
-    );
-    qbit_local.add_torrent(&add_torrent_arg).await?;
-    info!("[/inject-seedbox-torrents] inserted torrent into qbittorrent-local");
+    trace!("add_torrent_arg: {:?}", add_torrent_arg);
+    let added = if record.skips("qbittorrent-local", "add torrent") {
+        Ok(())
+    } else {
+        qbit_local.add_torrent(&add_torrent_arg).await
+    };
+    match &added {
+        Ok(_) => record.call("qbittorrent-local", "add torrent", true, download_id),
+        Err(err) => record.call("qbittorrent-local", "add torrent", false, err),
+    }
+    added?;
+    info!("inserted torrent into qbittorrent-local");
+
+    // give qbittorrent time to check the torrent
+    if !record.dry_run() {
+        sleep(Duration::from_secs(60)).await;
+    }
 
     if let Some(cross_seed_url) = cross_seed_url {
         let cross_seed_api_key = cross_seed_api_key.context("No API key for cross-seed found.")?;
 
-        sleep(Duration::from_secs(60)).await;
-
-        let webhook = WebhookRequest::InfoHash(download_id);
-        let resp = cross_seed_webhook(&cross_seed_url, &cross_seed_api_key, webhook).await?;
+        let webhook = WebhookRequest::InfoHash(download_id.to_string());
+        let resp = send_webhook(&cross_seed_url, &cross_seed_api_key, webhook, record).await?;
         if resp == StatusCode::from_u16(204).unwrap() {
-            info!("[/inject-seedbox-torrents] Succesfully called cross-seed.");
+            info!("Succesfully called cross-seed.");
         } else {
-            info!("[/inject-seedbox-torrents] Calling cross-seed failed!");
+            info!("Calling cross-seed failed!");
         }
     }
-    Ok(())
+
+    if record.dry_run() {
+        return Ok(true);
+    }
+    Ok(data_complete(&qbit_local, download_id).await)
+}
+
+/// Whether qbittorrent has all data of the torrent after checking it. Errors count as complete,
+/// they should not turn into a notification about missing data.
+async fn data_complete(qbit: &Qbit, download_id: &str) -> bool {
+    let arg = GetTorrentListArg {
+        hashes: Some(download_id.to_string()),
+        ..Default::default()
+    };
+
+    match qbit.get_torrent_list(arg).await {
+        Ok(torrents) => torrents
+            .first()
+            .and_then(|torrent| torrent.progress)
+            .is_none_or(|progress| progress >= 1.0),
+        Err(err) => {
+            warn!("Could not get progress of torrent {download_id}: {err}");
+            true
+        }
+    }
+}
+
+/// Copy a single torrent from the seedbox into the local qbittorrent, stored as a new history
+/// entry. Used by the `inject` and `backfill-inject` commands.
+pub(crate) async fn run_inject(
+    state: Arc<RwLock<AppState>>,
+    hash: String,
+    category: Option<String>,
+) -> anyhow::Result<()> {
+    let fields = EntryFields {
+        download_id: Some(hash.clone()),
+        ..Default::default()
+    };
+    let payload = serde_json::json!({ "hash": hash, "category": category });
+    let origin = Origin::command_line(&state);
+    let record = db(&state)?.start("cli:inject", &origin, fields, &payload)?;
+
+    let job = {
+        let state = state.clone();
+        |record: Record| async move {
+            let release = Release {
+                download_id: Some(hash.clone()),
+                ..Default::default()
+            };
+            let result = inject_torrent(&state, None, &hash, category, &record).await;
+            notify_inject(&state, &record, release, &result);
+            result.map(|_| ())
+        }
+    };
+    run_recorded_job(&state, record, job).await
 }
 
 pub(crate) async fn inject_seedbox_torrents_radarr(
     State(state): State<Arc<RwLock<AppState>>>,
-    Json(payload): Json<RadarrConnectWebhook>,
+    origin: Origin,
+    instance: Option<extract::Path<String>>,
+    Json(mut payload): Json<RadarrConnectWebhook>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    trace!("[/inject-seedbox-torrents] payload: {payload:?}");
-    match inject_seedbox_torrents(ArrConnectWebhook::Radarr(payload), state).await {
-        Ok(_) => Ok(StatusCode::OK),
+    // the instance in the route wins and is stored with the request for replays
+    if let Some(instance) = route_instance(&state, instance.map(|extract::Path(i)| i))? {
+        payload.instance_name = Some(instance);
+    }
+    trace!("payload: {payload:?}");
+    let request = ArrImport::from(&payload);
+    let fields = EntryFields::from(&request);
+    let record = record_request(
+        &state,
+        "/inject-seedbox-torrents-radarr",
+        &origin,
+        fields,
+        &payload,
+    );
+
+    let job = {
+        let state = state.clone();
+        |record: Record| async move { inject_seedbox_torrents(request, state, &record).await }
+    };
+    match run_recorded_job(&state, record.clone(), job).await {
+        Ok(_) => Ok(finished_response(&record)),
         Err(err) => {
             error!("Error occured: {err}");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -226,11 +404,31 @@ pub(crate) async fn inject_seedbox_torrents_radarr(
 
 pub(crate) async fn inject_seedbox_torrents_sonarr(
     State(state): State<Arc<RwLock<AppState>>>,
-    Json(payload): Json<SonarrConnectWebhook>,
+    origin: Origin,
+    instance: Option<extract::Path<String>>,
+    Json(mut payload): Json<SonarrConnectWebhook>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    trace!("[/inject-seedbox-torrents] payload: {payload:?}");
-    match inject_seedbox_torrents(ArrConnectWebhook::Sonarr(payload), state).await {
-        Ok(_) => Ok(StatusCode::OK),
+    // the instance in the route wins and is stored with the request for replays
+    if let Some(instance) = route_instance(&state, instance.map(|extract::Path(i)| i))? {
+        payload.instance_name = Some(instance);
+    }
+    trace!("payload: {payload:?}");
+    let request = ArrImport::from(&payload);
+    let fields = EntryFields::from(&request);
+    let record = record_request(
+        &state,
+        "/inject-seedbox-torrents-sonarr",
+        &origin,
+        fields,
+        &payload,
+    );
+
+    let job = {
+        let state = state.clone();
+        |record: Record| async move { inject_seedbox_torrents(request, state, &record).await }
+    };
+    match run_recorded_job(&state, record.clone(), job).await {
+        Ok(_) => Ok(finished_response(&record)),
         Err(err) => {
             error!("Error occured: {err}");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
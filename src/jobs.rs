@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+
+use tracing::{info, Instrument};
+
+use anyhow::anyhow;
+
+use tokio::task::AbortHandle;
+
+use crate::db::Record;
+use crate::history::db;
+use crate::AppState;
+
+/// Abort handles of all currently running jobs, keyed by their history id.
+pub(crate) type Jobs = Arc<Mutex<HashMap<i64, AbortHandle>>>;
+
+fn jobs(state: &Arc<RwLock<AppState>>) -> anyhow::Result<Jobs> {
+    let read_guard = state
+        .read()
+        .map_err(|_| anyhow!("Could not read from state."))?;
+
+    Ok(read_guard.jobs.clone())
+}
+
+/// Run `job` in its own task so it can be cancelled and keeps running if the client disconnects.
+/// The job itself is responsible for finishing its history entry. Jobs without a history entry
+/// can't be cancelled.
+pub(crate) async fn run_job<F, T>(
+    state: &Arc<RwLock<AppState>>,
+    id: Option<i64>,
+    job: F,
+) -> anyhow::Result<T>
+where
+    F: Future<Output = anyhow::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let jobs = jobs(state)?;
+
+    let handle = tokio::spawn(job.in_current_span());
+    if let Some(id) = id {
+        jobs.lock()
+            .map_err(|_| anyhow!("Could not lock jobs."))?
+            .insert(id, handle.abort_handle());
+    }
+
+    let result = handle.await;
+
+    if let (Some(id), Ok(mut jobs)) = (id, jobs.lock()) {
+        jobs.remove(&id);
+    }
+
+    match result {
+        Ok(result) => result,
+        Err(err) if err.is_cancelled() => Err(anyhow!("Job was cancelled.")),
+        Err(err) => Err(anyhow!("Job failed: {err}")),
+    }
+}
+
+/// Like `run_job`, finishing the history entry of `record` with the outcome of the job.
+pub(crate) async fn run_recorded_job<F, Fut, T>(
+    state: &Arc<RwLock<AppState>>,
+    record: Record,
+    job: F,
+) -> anyhow::Result<T>
+where
+    F: FnOnce(Record) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let id = record.id();
+    let job = job(record.clone());
+
+    run_job(state, id, async move {
+        let result = job.await;
+        record.finish(&result);
+        result
+    })
+    .await
+}
+
+/// Cancel the running job with the given history id. Returns `false` if no such job is running,
+/// a job that finished in the meantime keeps its outcome.
+pub(crate) fn cancel_job(state: &Arc<RwLock<AppState>>, id: i64) -> anyhow::Result<bool> {
+    let handle = jobs(state)?
+        .lock()
+        .map_err(|_| anyhow!("Could not lock jobs."))?
+        .remove(&id);
+
+    let Some(handle) = handle else {
+        return Ok(false);
+    };
+
+    handle.abort();
+    if !db(state)?.cancel(id, "Cancelled by user.")? {
+        info!("Job {id} already finished, not cancelled.");
+        return Ok(false);
+    }
+    info!("Cancelled job {id}.");
+
+    Ok(true)
+}
+
+/// Ids of all currently running jobs.
+pub(crate) fn running_jobs(state: &Arc<RwLock<AppState>>) -> anyhow::Result<Vec<i64>> {
+    let ids = jobs(state)?
+        .lock()
+        .map_err(|_| anyhow!("Could not lock jobs."))?
+        .keys()
+        .copied()
+        .collect();
+
+    Ok(ids)
+}
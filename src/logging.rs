@@ -0,0 +1,81 @@
+use std::env;
+
+use tracing::{field::Empty, info_span, Instrument};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use axum::{
+    extract::Request,
+    http::HeaderValue,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use uuid::Uuid;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Id of the current request, available to handlers as an `Extension`.
+#[derive(Clone, Debug)]
+pub(crate) struct RequestId(pub String);
+
+/// Set up the global `tracing` subscriber. `LOG_FORMAT=json` switches to structured JSON output,
+/// everything else uses the human readable format. The filter is taken from `RUST_LOG`.
+pub(crate) fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let json = env::var("LOG_FORMAT")
+        .map(|format| format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+
+    if json {
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false),
+            )
+            .init();
+    } else {
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer())
+            .init();
+    }
+}
+
+// Middleware creating a span per incoming request
+pub(crate) async fn request_id_middleware(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // The empty fields get filled by the handlers once the payload has been parsed.
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        release_title = Empty,
+        download_id = Empty,
+        client = Empty,
+        api_key = Empty,
+    );
+
+    request
+        .extensions_mut()
+        .insert(RequestId(request_id.clone()));
+
+    let mut response = next.run(request).instrument(span).await.into_response();
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
@@ -1,37 +1,150 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use env_logger::Env;
-use log::{info, warn};
+use tracing::{info, warn};
+
+use anyhow::Context;
+
+use clap::{Parser, Subcommand};
 
 use axum::{
-    extract::{Request, State},
-    http::StatusCode,
-    middleware::{self, Next},
-    response::IntoResponse,
-    routing::post,
+    middleware,
+    routing::{get, post},
     Router,
 };
 
 mod data_types;
+mod db;
+mod logging;
 
 mod announce;
+mod arr;
+mod arr_events;
+mod arr_instances;
+mod auth;
+mod backfill;
+mod dashboard;
+mod discord;
+mod health;
+mod history;
 mod inject_seedbox_torrents;
+mod jobs;
+mod notify;
+mod tls;
 mod xseed;
-mod discord;
 
 mod cross_seed;
 
-use crate::announce::announce;
+use crate::announce::{announce, announce_command, Announce};
+use crate::arr_events::ArrEventOptions;
+use crate::arr_instances::{arr_instances_from_env, clients_from_env, ArrInstance};
+use crate::auth::{
+    allowlist_middleware, api_keys_from_env, auth_metrics, auth_middleware, auth_status,
+    client_cert_scopes_from_env, env_flag, AllowList, ApiKey, AuthFailures, AuthMethods, BanConfig,
+    KeyMetrics, Scope,
+};
+use crate::backfill::{backfill_inject_command, backfill_xseed_command, Arr};
+use crate::dashboard::{
+    dashboard, dashboard_cancel, dashboard_login, dashboard_login_form, dashboard_replay, Sessions,
+};
+use crate::db::Db;
+use crate::health::check_config_command;
+use crate::history::{db, history_get, history_list, history_replay, replay_command};
 use crate::inject_seedbox_torrents::{
-    inject_seedbox_torrents_radarr, inject_seedbox_torrents_sonarr,
+    inject_seedbox_torrents_radarr, inject_seedbox_torrents_sonarr, run_inject,
 };
-use crate::xseed::{xseed_radarr, xseed_sonarr};
+use crate::jobs::Jobs;
+use crate::notify::{
+    daily_digest, flush_notifications, notification_targets_from_env, DigestTime,
+    NotificationQueue, NotificationTarget, Release,
+};
+use crate::tls::{rustls_config, ClientCertAcceptor, TlsFiles};
+use crate::xseed::{xseed_command, xseed_radarr, xseed_sonarr, XseedTarget};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Log and record outbound calls which would change something instead of making them
+    #[arg(long, global = true)]
+    dry_run: bool,
+}
 
-#[derive(Clone, Default)]
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server (default)
+    Serve,
+    /// Replay stored xseed / inject requests
+    Replay {
+        /// Only replay requests which failed
+        #[arg(long)]
+        failed: bool,
+        /// How far back to look, e.g. `24h` or `3d`
+        #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+        since: Duration,
+    },
+    /// Announce a single release to all cross-seed instances
+    Announce(Announce),
+    /// Search cross-seeds for a single torrent or path with the local cross-seed
+    Xseed(XseedTarget),
+    /// Copy a single torrent from the seedbox into the local qbittorrent
+    Inject {
+        /// Info hash of the torrent on the seedbox
+        #[arg(long)]
+        hash: String,
+        /// Category in the local qbittorrent, defaults to the one on the seedbox
+        #[arg(long)]
+        category: Option<String>,
+    },
+    /// Inject all seedbox torrents of a category whose data already exists locally
+    BackfillInject {
+        /// Category on the seedbox
+        #[arg(long)]
+        category: String,
+        /// Actually inject the torrents instead of only reporting what would be injected
+        #[arg(long)]
+        apply: bool,
+        /// Number of torrents injected at the same time
+        #[arg(long, default_value_t = 5)]
+        batch_size: usize,
+        /// Pause between two batches
+        #[arg(long, default_value = "1m", value_parser = humantime::parse_duration)]
+        pause: Duration,
+    },
+    /// Run xseed for all downloads imported into Sonarr / Radarr in a time range
+    BackfillXseed {
+        /// Only read the history of one of them, defaults to all configured
+        #[arg(long, value_enum)]
+        arr: Option<Arr>,
+        /// Start of the time range, e.g. `3d`
+        #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+        since: Duration,
+        /// End of the time range, defaults to now
+        #[arg(long, value_parser = humantime::parse_duration)]
+        until: Option<Duration>,
+        /// Pause between two xseeds
+        #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+        pace: Duration,
+    },
+    /// Validate the configuration and check all integrations are reachable
+    CheckConfig,
+}
+
+#[derive(Clone)]
 pub struct AppState {
-    api_key: String,
+    api_keys: Vec<ApiKey>,
+    auth_methods: AuthMethods,
+    auth_failures: Arc<AuthFailures>,
+    key_metrics: Arc<KeyMetrics>,
+    /// Scopes of clients authenticated with a TLS client certificate
+    client_cert_scopes: Vec<Scope>,
+    allow_list: AllowList,
+
+    db: Arc<Db>,
 
     cross_seed_seedbox_url: Option<String>,
     cross_seed_seedbox_api_key: Option<String>,
@@ -44,6 +157,18 @@ pub struct AppState {
 
     xseed_unique_ids: HashSet<String>,
 
+    /// Failed replays of a request before `replay` stops and notifies about it
+    replay_max_attempts: u32,
+
+    arr_event_options: ArrEventOptions,
+    /// Profiles of Sonarr / Radarr instances, keyed by `env_key` of their name
+    arr_instances: HashMap<String, ArrInstance>,
+    /// Releases of registered grabs by download id, with the time they were grabbed
+    grabs: HashMap<String, (i64, Release)>,
+
+    jobs: Jobs,
+    dashboard_sessions: Sessions,
+
     qbittorrent_local_host: Option<String>,
     qbittorrent_local_user: Option<String>,
     qbittorrent_local_password: Option<String>,
@@ -55,50 +180,31 @@ pub struct AppState {
     qbittorrent_local_dir: Option<String>,
     qbittorrent_seedbox_name: Option<String>,
 
-    discord_webhook_url: Option<String>,
-}
+    sonarr_url: Option<String>,
+    sonarr_api_key: Option<String>,
+    radarr_url: Option<String>,
+    radarr_api_key: Option<String>,
 
-// Middleware for authentication
-async fn auth_middleware(
-    State(state): State<Arc<RwLock<AppState>>>,
-    request: Request,
-    next: Next,
-) -> impl IntoResponse {
-    let api_key = request
-        .headers()
-        .get("X-Api-Key")
-        .and_then(|v| v.to_str().ok());
-
-    if api_key == Some(&state.read().unwrap().api_key) {
-        Ok(next.run(request).await)
-    } else {
-        warn!("UNAUTHORIZED request: {request:?}");
-        Err(StatusCode::UNAUTHORIZED)
-    }
+    notification_targets: Vec<NotificationTarget>,
+    notification_queue: NotificationQueue,
+
+    /// Handle all requests as dry runs, see `Origin::dry_run`
+    dry_run: bool,
 }
 
 fn create_config_state() -> anyhow::Result<Arc<RwLock<AppState>>> {
-    let xseed_torrent_clients = match env::var("XSEED_TORRENT_CLIENTS") {
-        Ok(torrent_clients) => Some(
-            torrent_clients
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect(),
-        ),
-        _ => None,
-    };
-    let xseed_usenet_clients = match env::var("XSEED_USENET_CLIENTS") {
-        Ok(usenet_clients) => Some(
-            usenet_clients
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect(),
-        ),
-        _ => None,
-    };
+    let db_path = env::var("HISTORY_DB_PATH").unwrap_or("cross-seed-tools.db".to_string());
+    let db = Db::open(&db_path).with_context(|| format!("Could not open database {db_path}"))?;
 
     let state = Arc::new(RwLock::new(AppState {
-        api_key: env::var("API_KEY")?,
+        api_keys: api_keys_from_env()?,
+        auth_methods: AuthMethods::from_env()?,
+        auth_failures: Arc::new(AuthFailures::new(BanConfig::from_env()?)),
+        key_metrics: Arc::new(KeyMetrics::default()),
+        client_cert_scopes: client_cert_scopes_from_env()?,
+        allow_list: AllowList::from_env()?,
+
+        db: Arc::new(db),
 
         cross_seed_seedbox_url: env::var("CROSS_SEED_SEEDBOX_URL").ok(),
         cross_seed_seedbox_api_key: env::var("CROSS_SEED_SEEDBOX_API_KEY").ok(),
@@ -106,11 +212,23 @@ fn create_config_state() -> anyhow::Result<Arc<RwLock<AppState>>> {
         cross_seed_local_url: env::var("CROSS_SEED_LOCAL_URL").ok(),
         cross_seed_local_api_key: env::var("CROSS_SEED_LOCAL_API_KEY").ok(),
 
-        xseed_torrent_clients,
-        xseed_usenet_clients,
+        xseed_torrent_clients: clients_from_env("XSEED_TORRENT_CLIENTS"),
+        xseed_usenet_clients: clients_from_env("XSEED_USENET_CLIENTS"),
 
         xseed_unique_ids: HashSet::new(),
 
+        replay_max_attempts: match env::var("REPLAY_MAX_ATTEMPTS") {
+            Ok(value) => value.parse().context("Invalid REPLAY_MAX_ATTEMPTS")?,
+            Err(_) => 3,
+        },
+
+        arr_event_options: ArrEventOptions::from_env()?,
+        arr_instances: arr_instances_from_env()?,
+        grabs: HashMap::new(),
+
+        jobs: Jobs::default(),
+        dashboard_sessions: Sessions::from_env()?,
+
         qbittorrent_local_host: env::var("QBITTORRENT_LOCAL_HOST").ok(),
         qbittorrent_local_user: env::var("QBITTORRENT_LOCAL_USER").ok(),
         qbittorrent_local_password: env::var("QBITTORRENT_LOCAL_PASSWORD").ok(),
@@ -122,40 +240,132 @@ fn create_config_state() -> anyhow::Result<Arc<RwLock<AppState>>> {
         qbittorrent_local_dir: env::var("QBITTORRENT_LOCAL_DIR").ok(),
         qbittorrent_seedbox_host: env::var("QBITTORRENT_SEEDBOX_HOST").ok(),
 
-        discord_webhook_url: env::var("DISCORD_WEBHOOK_URL").ok(),
+        sonarr_url: env::var("SONARR_URL").ok(),
+        sonarr_api_key: env::var("SONARR_API_KEY").ok(),
+        radarr_url: env::var("RADARR_URL").ok(),
+        radarr_api_key: env::var("RADARR_API_KEY").ok(),
+
+        notification_targets: notification_targets_from_env()?,
+        notification_queue: NotificationQueue::from_env()?,
+
+        dry_run: env_flag("DRY_RUN", false)?,
     }));
     Ok(state)
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-    dotenvy::dotenv()?;
-
+async fn serve(state: Arc<RwLock<AppState>>) -> anyhow::Result<()> {
     let host = &env::var("HOST")?;
-    let state = create_config_state()?;
+
+    db(&state)?.fail_interrupted()?;
+
+    if let Some(time) = DigestTime::from_env()? {
+        tokio::spawn(daily_digest(state.clone(), time));
+    }
 
     let router = Router::new()
         .route("/announce", post(announce))
         .route("/xseed-sonarr", post(xseed_sonarr))
+        .route("/xseed-sonarr/{instance}", post(xseed_sonarr))
         .route("/xseed-radarr", post(xseed_radarr))
+        .route("/xseed-radarr/{instance}", post(xseed_radarr))
         .route(
             "/inject-seedbox-torrents-sonarr",
             post(inject_seedbox_torrents_sonarr),
         )
+        .route(
+            "/inject-seedbox-torrents-sonarr/{instance}",
+            post(inject_seedbox_torrents_sonarr),
+        )
         .route(
             "/inject-seedbox-torrents-radarr",
             post(inject_seedbox_torrents_radarr),
         )
+        .route(
+            "/inject-seedbox-torrents-radarr/{instance}",
+            post(inject_seedbox_torrents_radarr),
+        )
+        .route("/history", get(history_list))
+        .route("/history/{id}", get(history_get))
+        .route("/history/{id}/replay", post(history_replay))
+        .route("/status/auth", get(auth_status))
+        .route("/status/metrics", get(auth_metrics))
+        .route("/dashboard", get(dashboard))
+        .route("/dashboard/history/{id}/replay", post(dashboard_replay))
+        .route("/dashboard/jobs/{id}/cancel", post(dashboard_cancel))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ))
+        .route(
+            "/dashboard/login",
+            get(dashboard_login_form).post(dashboard_login),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            allowlist_middleware,
+        ))
+        .layer(middleware::from_fn(logging::request_id_middleware))
         .with_state(state);
     let listener = tokio::net::TcpListener::bind(host).await.unwrap();
-
-    info!("Run server on {host}...");
-    axum::serve(listener, router).await.unwrap();
+    let service = router.into_make_service_with_connect_info::<SocketAddr>();
+
+    match TlsFiles::from_env()? {
+        Some(files) => {
+            let config = rustls_config(files)?;
+
+            info!("Run server on {host} with TLS...");
+            axum_server::from_tcp(listener.into_std()?)
+                .acceptor(ClientCertAcceptor::new(config))
+                .serve(service)
+                .await?;
+        }
+        None => {
+            info!("Run server on {host}...");
+            axum::serve(listener, service).await.unwrap();
+        }
+    }
 
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    dotenvy::dotenv()?;
+    logging::init();
+
+    let state = create_config_state()?;
+    if cli.dry_run {
+        state.write().unwrap().dry_run = true;
+    }
+    if state.read().unwrap().dry_run {
+        warn!("Dry run, outbound calls which change something are only logged.");
+    }
+
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(state.clone()).await,
+        Command::Replay { failed, since } => replay_command(state.clone(), failed, since).await,
+        Command::Announce(announce) => announce_command(state.clone(), announce).await,
+        Command::Xseed(target) => xseed_command(state.clone(), target).await,
+        Command::Inject { hash, category } => run_inject(state.clone(), hash, category).await,
+        Command::BackfillInject {
+            category,
+            apply,
+            batch_size,
+            pause,
+        } => backfill_inject_command(state.clone(), category, apply, batch_size, pause).await,
+        Command::BackfillXseed {
+            arr,
+            since,
+            until,
+            pace,
+        } => backfill_xseed_command(state.clone(), arr, since, until, pace).await,
+        Command::CheckConfig => check_config_command(state.clone()).await,
+    };
+
+    // commands exit before batch windows end
+    flush_notifications(&state).await;
+
+    result
+}
@@ -0,0 +1,276 @@
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+use serde_json::json;
+
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, StatusCode};
+
+use super::Event;
+use crate::discord::discord_embed;
+
+pub(crate) type NotifyFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+
+/// A service notifications can be sent to.
+pub(crate) trait Notifier: Send + Sync {
+    /// Type of the notifier as used in the configuration, e.g. `ntfy`
+    fn kind(&self) -> &'static str;
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a>;
+}
+
+/// Wait used for rate limits without `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Error of notifiers which were asked by the service to retry later.
+#[derive(Debug)]
+pub(crate) struct RetryAfter(pub Duration);
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rate limited, retry after {:.1}s", self.0.as_secs_f64())
+    }
+}
+
+impl std::error::Error for RetryAfter {}
+
+/// Send `request` and fail on non-success status codes. Rate limits fail with `RetryAfter`.
+async fn send_request(request: RequestBuilder) -> anyhow::Result<()> {
+    let response = request.send().await?;
+
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_RETRY_AFTER);
+        return Err(RetryAfter(retry_after).into());
+    }
+    response.error_for_status()?;
+
+    Ok(())
+}
+
+/// Discord webhook, sent as embed.
+pub(crate) struct Discord {
+    pub webhook_url: String,
+}
+
+impl Notifier for Discord {
+    fn kind(&self) -> &'static str {
+        "discord"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        Box::pin(discord_embed(&self.webhook_url, event))
+    }
+}
+
+/// ntfy topic, `url` includes the topic, e.g. `https://ntfy.sh/cross-seed-tools`.
+pub(crate) struct Ntfy {
+    pub url: String,
+    /// Access token for protected topics
+    pub token: Option<String>,
+}
+
+impl Notifier for Ntfy {
+    fn kind(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        let mut request = Client::new()
+            .post(&self.url)
+            .header("Title", event.title())
+            .header(
+                "Tags",
+                if event.kind.success() {
+                    "white_check_mark"
+                } else {
+                    "x"
+                },
+            )
+            .header("Priority", if event.kind.success() { "3" } else { "4" })
+            .body(event.message());
+
+        if let Some(link) = &event.release.link {
+            request = request.header("Click", link);
+        }
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        Box::pin(send_request(request))
+    }
+}
+
+/// Gotify server, messages are sent with an application token.
+pub(crate) struct Gotify {
+    pub url: String,
+    pub token: String,
+}
+
+impl Notifier for Gotify {
+    fn kind(&self) -> &'static str {
+        "gotify"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        let request = Client::new()
+            .post(format!("{}/message", self.url.trim_end_matches('/')))
+            .header("X-Gotify-Key", &self.token)
+            .json(&json!({
+                "title": event.title(),
+                "message": event.message(),
+                "priority": if event.kind.success() { 5 } else { 8 },
+            }));
+
+        Box::pin(send_request(request))
+    }
+}
+
+/// Telegram bot writing to a single chat.
+pub(crate) struct Telegram {
+    /// Bot API server, `https://api.telegram.org` unless self-hosted
+    pub url: String,
+    pub token: String,
+    pub chat_id: String,
+}
+
+impl Notifier for Telegram {
+    fn kind(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        let request = Client::new()
+            .post(format!(
+                "{}/bot{}/sendMessage",
+                self.url.trim_end_matches('/'),
+                self.token
+            ))
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": format!("{}\n\n{}", event.title(), event.message()),
+                "disable_web_page_preview": true,
+            }));
+
+        Box::pin(send_request(request))
+    }
+}
+
+/// Slack incoming webhook.
+pub(crate) struct Slack {
+    pub webhook_url: String,
+}
+
+impl Notifier for Slack {
+    fn kind(&self) -> &'static str {
+        "slack"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        let request = Client::new().post(&self.webhook_url).json(&json!({
+            "text": format!("*{}*\n{}", event.title(), event.message()),
+        }));
+
+        Box::pin(send_request(request))
+    }
+}
+
+/// Apprise API, `url` is the notify endpoint, e.g. `http://apprise:8000/notify/cross-seed-tools`.
+pub(crate) struct Apprise {
+    pub url: String,
+}
+
+impl Notifier for Apprise {
+    fn kind(&self) -> &'static str {
+        "apprise"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        let request = Client::new().post(&self.url).json(&json!({
+            "title": event.title(),
+            "body": event.message(),
+            "type": if event.kind.success() { "success" } else { "failure" },
+        }));
+
+        Box::pin(send_request(request))
+    }
+}
+
+/// Any service accepting JSON, the event is posted as it is.
+pub(crate) struct Webhook {
+    pub url: String,
+    /// Sent as bearer token
+    pub token: Option<String>,
+}
+
+impl Notifier for Webhook {
+    fn kind(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> NotifyFuture<'a> {
+        let mut request = Client::new().post(&self.url).json(&json!({
+            "event": event.kind.as_str(),
+            "title": event.title(),
+            "success": event.kind.success(),
+            "release": event.release,
+            "targets": event.targets,
+            "error": event.error,
+        }));
+
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        Box::pin(send_request(request))
+    }
+}
+
+/// Create a notifier of type `kind`, reading its settings from `<prefix>_URL`, `<prefix>_TOKEN`
+/// and `<prefix>_CHAT_ID`.
+pub(crate) fn notifier_from_env(kind: &str, prefix: &str) -> anyhow::Result<Box<dyn Notifier>> {
+    let setting = |name: &str| env::var(format!("{prefix}_{name}")).ok();
+    let required =
+        |name: &str| setting(name).with_context(|| format!("{prefix}_{name} is not set"));
+
+    let notifier: Box<dyn Notifier> = match kind {
+        "discord" => Box::new(Discord {
+            webhook_url: required("URL")?,
+        }),
+        "ntfy" => Box::new(Ntfy {
+            url: required("URL")?,
+            token: setting("TOKEN"),
+        }),
+        "gotify" => Box::new(Gotify {
+            url: required("URL")?,
+            token: required("TOKEN")?,
+        }),
+        "telegram" => Box::new(Telegram {
+            url: setting("URL").unwrap_or("https://api.telegram.org".to_string()),
+            token: required("TOKEN")?,
+            chat_id: required("CHAT_ID")?,
+        }),
+        "slack" => Box::new(Slack {
+            webhook_url: required("URL")?,
+        }),
+        "apprise" => Box::new(Apprise {
+            url: required("URL")?,
+        }),
+        "webhook" => Box::new(Webhook {
+            url: required("URL")?,
+            token: setting("TOKEN"),
+        }),
+        kind => bail!("Unknown notifier type {kind}"),
+    };
+
+    Ok(notifier)
+}
@@ -0,0 +1,91 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{Event, EventKind};
+
+/// Releases listed in a batch, Discord cuts off long messages.
+const MAX_LISTED: usize = 25;
+
+/// Events of a notification target waiting to be sent together.
+#[derive(Debug, Default)]
+pub(crate) struct Batch {
+    pub window: Duration,
+    pending: Mutex<Vec<Event>>,
+}
+
+impl Batch {
+    pub(crate) fn new(window: Duration) -> Self {
+        Batch {
+            window,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Queue `event`. Returns `true` if it started a new batch.
+    pub(crate) fn push(&self, event: Event) -> bool {
+        let Ok(mut pending) = self.pending.lock() else {
+            return false;
+        };
+
+        pending.push(event);
+        pending.len() == 1
+    }
+
+    /// Take all queued events, combined into a single event.
+    pub(crate) fn take(&self) -> Option<Event> {
+        let events = match self.pending.lock() {
+            Ok(mut pending) => std::mem::take(&mut *pending),
+            Err(_) => return None,
+        };
+
+        summarize(events, self.window)
+    }
+}
+
+/// Combine `events` into one, e.g. "12× cross-seed completed successfully in the last 5m".
+/// A single event is sent as it is.
+fn summarize(mut events: Vec<Event>, window: Duration) -> Option<Event> {
+    if events.len() <= 1 {
+        return events.pop();
+    }
+
+    let mut counts: Vec<(EventKind, usize)> = Vec::new();
+    for event in &events {
+        match counts.iter_mut().find(|(kind, _)| *kind == event.kind) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((event.kind, 1)),
+        }
+    }
+
+    let window = humantime::format_duration(window);
+    let headline = match counts.as_slice() {
+        [(kind, count)] => format!("{count}× {} in the last {window}", kind.headline()),
+        _ => format!("{} notifications in the last {window}", events.len()),
+    };
+
+    let mut message = String::new();
+    if counts.len() > 1 {
+        for (kind, count) in &counts {
+            let _ = writeln!(message, "{count}× {}", kind.headline());
+        }
+        message.push('\n');
+    }
+    for event in events.iter().take(MAX_LISTED) {
+        let _ = writeln!(
+            message,
+            "{} {}",
+            if event.kind.success() { "✅" } else { "❌" },
+            event.title()
+        );
+    }
+    if events.len() > MAX_LISTED {
+        let _ = writeln!(message, "… and {} more", events.len() - MAX_LISTED);
+    }
+
+    Some(Event::summary(
+        EventKind::Batch,
+        headline,
+        message.trim_end().to_string(),
+    ))
+}
@@ -0,0 +1,132 @@
+use std::env;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info};
+
+use anyhow::{bail, Context};
+
+use super::{notify_unrecorded, Event, EventKind};
+use crate::db::{now, HistorySummary, Status};
+use crate::history::db;
+use crate::AppState;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Time of day (UTC) the daily digest is sent at.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DigestTime {
+    /// Seconds since midnight
+    offset: u64,
+}
+
+impl DigestTime {
+    /// Read the time from `NOTIFY_DIGEST_TIME`, e.g. `08:00`. Returns `None` if the digest is
+    /// disabled.
+    pub(crate) fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(time) = env::var("NOTIFY_DIGEST_TIME") else {
+            return Ok(None);
+        };
+
+        let (hours, minutes) = time
+            .trim()
+            .split_once(':')
+            .context("NOTIFY_DIGEST_TIME has to be in the format HH:MM")?;
+        let hours: u64 = hours
+            .parse()
+            .context("Invalid hours in NOTIFY_DIGEST_TIME")?;
+        let minutes: u64 = minutes
+            .parse()
+            .context("Invalid minutes in NOTIFY_DIGEST_TIME")?;
+
+        if hours > 23 || minutes > 59 {
+            bail!("NOTIFY_DIGEST_TIME {time} is not a valid time");
+        }
+
+        Ok(Some(DigestTime {
+            offset: hours * 60 * 60 + minutes * 60,
+        }))
+    }
+
+    fn until_next(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let today = now % DAY;
+
+        let seconds = if self.offset > today {
+            self.offset - today
+        } else {
+            DAY - today + self.offset
+        };
+        Duration::from_secs(seconds)
+    }
+}
+
+/// Successful and failed requests of all endpoints matching `group`.
+fn counts(summary: &HistorySummary, group: impl Fn(&str) -> bool) -> (i64, i64) {
+    summary
+        .requests
+        .iter()
+        .filter(|(endpoint, _, _)| group(endpoint))
+        .fold(
+            (0, 0),
+            |(success, failed), (_, status, count)| match status {
+                Status::Success => (success + count, failed),
+                Status::Failed | Status::Cancelled => (success, failed + count),
+                Status::Running => (success, failed),
+            },
+        )
+}
+
+fn digest_message(summary: &HistorySummary) -> String {
+    let mut message = String::new();
+
+    let (accepted, rejected) = counts(summary, |endpoint| endpoint == "/announce");
+    let _ = writeln!(
+        message,
+        "Announces: {accepted} accepted, {rejected} not accepted"
+    );
+    for (instance, count) in &summary.announces_accepted {
+        let _ = writeln!(message, "  {instance}: {count}");
+    }
+
+    let (success, failed) = counts(summary, |endpoint| {
+        endpoint.starts_with("/xseed-") || endpoint == "cli:xseed"
+    });
+    let _ = writeln!(message, "xseed: {success} succeeded, {failed} failed");
+
+    let (success, failed) = counts(summary, |endpoint| {
+        endpoint.starts_with("/inject-seedbox-torrents-") || endpoint == "cli:inject"
+    });
+    let _ = writeln!(message, "Injections: {success} succeeded, {failed} failed");
+
+    message.trim_end().to_string()
+}
+
+async fn send_digest(state: &Arc<RwLock<AppState>>) -> anyhow::Result<()> {
+    let summary = db(state)?.summary(now() - DAY as i64)?;
+
+    let event = Event::summary(
+        EventKind::DailyDigest,
+        EventKind::DailyDigest.headline().to_string(),
+        digest_message(&summary),
+    );
+    notify_unrecorded(state, event);
+
+    info!("Queued daily digest.");
+    Ok(())
+}
+
+/// Send a summary of the last 24 hours every day at `time`.
+pub(crate) async fn daily_digest(state: Arc<RwLock<AppState>>, time: DigestTime) {
+    loop {
+        tokio::time::sleep(time.until_next()).await;
+
+        if let Err(err) = send_digest(&state).await {
+            error!("Could not send daily digest: {err}");
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use anyhow::{bail, Context};
+
+use serde::Serialize;
+
+use crate::data_types::ArrImport;
+use crate::db::{OutboundCall, Record};
+use crate::AppState;
+
+mod backends;
+mod batch;
+mod digest;
+mod queue;
+mod templates;
+
+use backends::{notifier_from_env, Discord, Notifier};
+use batch::Batch;
+use templates::Templates;
+
+pub(crate) use backends::RetryAfter;
+pub(crate) use digest::{daily_digest, DigestTime};
+pub(crate) use queue::NotificationQueue;
+
+/// Everything the endpoints can notify about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum EventKind {
+    AnnounceAccepted,
+    AnnounceRejected,
+    XseedOk,
+    XseedFailed,
+    InjectOk,
+    InjectFailed,
+    /// The torrent was injected, but its data is not complete locally yet
+    InjectWaitingForData,
+    /// Replaying a failed request failed again
+    RetryExhausted,
+    /// Summary of the last day, see `digest`
+    DailyDigest,
+    /// Events of a notification target collected over its batch window, not selectable
+    Batch,
+}
+
+const ALL_EVENTS: &[EventKind] = &[
+    EventKind::AnnounceAccepted,
+    EventKind::AnnounceRejected,
+    EventKind::XseedOk,
+    EventKind::XseedFailed,
+    EventKind::InjectOk,
+    EventKind::InjectFailed,
+    EventKind::InjectWaitingForData,
+    EventKind::RetryExhausted,
+    EventKind::DailyDigest,
+];
+
+impl EventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::AnnounceAccepted => "announce-accepted",
+            EventKind::AnnounceRejected => "announce-rejected",
+            EventKind::XseedOk => "xseed-ok",
+            EventKind::XseedFailed => "xseed-failed",
+            EventKind::InjectOk => "inject-ok",
+            EventKind::InjectFailed => "inject-failed",
+            EventKind::InjectWaitingForData => "inject-waiting-for-data",
+            EventKind::RetryExhausted => "retry-exhausted",
+            EventKind::DailyDigest => "daily-digest",
+            EventKind::Batch => "batch",
+        }
+    }
+
+    fn parse(event: &str) -> anyhow::Result<Vec<EventKind>> {
+        let event = event.to_lowercase();
+        if event == "all" || event == "*" {
+            return Ok(ALL_EVENTS.to_vec());
+        }
+
+        match ALL_EVENTS.iter().find(|kind| kind.as_str() == event) {
+            Some(kind) => Ok(vec![*kind]),
+            None => bail!("Unknown notification event {event}"),
+        }
+    }
+
+    /// Parse a comma-separated list of events.
+    pub(crate) fn parse_list(events: &str) -> anyhow::Result<HashSet<EventKind>> {
+        Ok(events
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(EventKind::parse)
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .concat()
+            .into_iter()
+            .collect())
+    }
+
+    pub(crate) fn headline(&self) -> &'static str {
+        match self {
+            EventKind::AnnounceAccepted => "Release accepted by cross-seed",
+            EventKind::AnnounceRejected => "Release not accepted by cross-seed",
+            EventKind::XseedOk => "cross-seed completed successfully",
+            EventKind::XseedFailed => "cross-seed failed",
+            EventKind::InjectOk => "Torrent injected into local qbittorrent",
+            EventKind::InjectFailed => "Injecting torrent failed",
+            EventKind::InjectWaitingForData => "Injected torrent is waiting for data",
+            EventKind::RetryExhausted => "Replay failed",
+            EventKind::DailyDigest => "Daily digest",
+            EventKind::Batch => "Notifications",
+        }
+    }
+
+    pub(crate) fn success(&self) -> bool {
+        matches!(
+            self,
+            EventKind::AnnounceAccepted
+                | EventKind::XseedOk
+                | EventKind::InjectOk
+                | EventKind::DailyDigest
+                | EventKind::Batch
+        )
+    }
+}
+
+/// Read the default events to notify about from `NOTIFY_EVENTS`. Defaults to all events but
+/// `announce-rejected`, cross-seed rejects most announces.
+fn notify_events_from_env() -> anyhow::Result<HashSet<EventKind>> {
+    match env::var("NOTIFY_EVENTS") {
+        Ok(events) => EventKind::parse_list(&events),
+        Err(_) => Ok(ALL_EVENTS
+            .iter()
+            .copied()
+            .filter(|kind| *kind != EventKind::AnnounceRejected)
+            .collect()),
+    }
+}
+
+/// Details of the release an event is about, as far as they are known.
+#[derive(Serialize, Debug, Clone, Default)]
+pub(crate) struct Release {
+    pub release_title: Option<String>,
+    pub indexer: Option<String>,
+    pub release_type: Option<String>,
+    pub client: Option<String>,
+    pub download_id: Option<String>,
+    pub poster: Option<String>,
+    /// Link to the series / movie
+    pub link: Option<String>,
+}
+
+impl Release {
+    /// Fill in details missing here from `other`, e.g. the indexer imports don't always send.
+    pub(crate) fn complete_from(&mut self, other: &Release) {
+        let fields = [
+            (&mut self.release_title, &other.release_title),
+            (&mut self.indexer, &other.indexer),
+            (&mut self.release_type, &other.release_type),
+            (&mut self.client, &other.client),
+            (&mut self.poster, &other.poster),
+            (&mut self.link, &other.link),
+        ];
+        for (field, other) in fields {
+            if field.as_ref().is_none_or(String::is_empty) {
+                field.clone_from(other);
+            }
+        }
+    }
+}
+
+impl From<&ArrImport> for Release {
+    fn from(request: &ArrImport) -> Self {
+        Release {
+            release_title: request.release_title.clone(),
+            indexer: request.indexer.clone(),
+            release_type: request.release_type.clone(),
+            client: request.download_client.clone(),
+            download_id: request.download_id.clone(),
+            poster: request.poster.clone(),
+            link: request.link.clone(),
+        }
+    }
+}
+
+/// Something worth notifying about which happened while handling a request.
+#[derive(Debug, Clone)]
+pub(crate) struct Event {
+    pub kind: EventKind,
+    pub release: Release,
+    /// Outbound calls made for the request, shown as results per target instance
+    pub targets: Vec<OutboundCall>,
+    pub error: Option<String>,
+    /// Message rendered from the template of the target the event is sent to, or the text of
+    /// summaries
+    pub custom_message: Option<String>,
+    /// Replaces the headline of the event kind
+    pub custom_headline: Option<String>,
+}
+
+impl Event {
+    pub(crate) fn new(kind: EventKind, release: Release) -> Self {
+        Event {
+            kind,
+            release,
+            targets: Vec::new(),
+            error: None,
+            custom_message: None,
+            custom_headline: None,
+        }
+    }
+
+    pub(crate) fn with_error(mut self, error: &anyhow::Error) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+
+    /// An event which only consists of a headline and a message, e.g. a summary.
+    pub(crate) fn summary(kind: EventKind, headline: String, message: String) -> Self {
+        Event {
+            custom_message: Some(message),
+            custom_headline: Some(headline),
+            ..Event::new(kind, Release::default())
+        }
+    }
+
+    pub(crate) fn headline(&self) -> &str {
+        self.custom_headline
+            .as_deref()
+            .unwrap_or(self.kind.headline())
+    }
+
+    pub(crate) fn title(&self) -> String {
+        match &self.release.release_title {
+            Some(release_title) => format!("{} ({release_title})", self.headline()),
+            None => self.headline().to_string(),
+        }
+    }
+
+    /// Plain text details for notifiers without rich formatting, unless a template was used.
+    pub(crate) fn message(&self) -> String {
+        if let Some(message) = &self.custom_message {
+            return message.clone();
+        }
+
+        let release = &self.release;
+        let mut message = String::new();
+
+        let fields = [
+            ("Release", &release.release_title),
+            ("Indexer", &release.indexer),
+            ("Release type", &release.release_type),
+            ("Client", &release.client),
+            ("Download ID", &release.download_id),
+            ("Error", &self.error),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value.as_ref().filter(|v| !v.is_empty()) {
+                let _ = writeln!(message, "{name}: {value}");
+            }
+        }
+
+        for call in &self.targets {
+            let _ = writeln!(
+                message,
+                "{} {} {}: {}",
+                if call.success { "✅" } else { "❌" },
+                call.target,
+                call.action,
+                call.detail.as_deref().unwrap_or_default()
+            );
+        }
+
+        if let Some(link) = &release.link {
+            let _ = writeln!(message, "{link}");
+        }
+
+        message.trim_end().to_string()
+    }
+}
+
+/// A configured notification channel and the events sent to it.
+#[derive(Clone)]
+pub(crate) struct NotificationTarget {
+    pub name: String,
+    notifier: Arc<dyn Notifier>,
+    events: HashSet<EventKind>,
+    templates: Templates,
+    /// Collects events over a window before sending them
+    batch: Option<Arc<Batch>>,
+}
+
+impl NotificationTarget {
+    pub(crate) fn kind(&self) -> &'static str {
+        self.notifier.kind()
+    }
+
+    /// Send `event` once, rendered with the template of this target if there is one.
+    async fn deliver(&self, event: &Event) -> anyhow::Result<()> {
+        let mut event = event.clone();
+
+        match self.templates.render(&event) {
+            Ok(Some(message)) => event.custom_message = Some(message),
+            Ok(None) => {}
+            Err(err) => warn!("Could not render template of {}: {err}", self.name),
+        }
+
+        self.notifier.send(&event).await
+    }
+}
+
+/// Parse a batch window, `0s` disables batching.
+fn batch_from_env(var: &str, default: Option<Duration>) -> anyhow::Result<Option<Duration>> {
+    let window = match env::var(var) {
+        Ok(window) => {
+            humantime::parse_duration(&window).with_context(|| format!("Invalid {var}"))?
+        }
+        Err(_) => return Ok(default),
+    };
+
+    Ok(Some(window).filter(|window| !window.is_zero()))
+}
+
+/// Read the notification targets from the environment. `NOTIFIERS` lists their names, each one
+/// is configured with `NOTIFIER_<NAME>_TYPE`, its settings and optionally
+/// `NOTIFIER_<NAME>_EVENTS`, templates and `NOTIFIER_<NAME>_BATCH_WINDOW`. `DISCORD_WEBHOOK_URL`
+/// adds a Discord target named `discord`.
+pub(crate) fn notification_targets_from_env() -> anyhow::Result<Vec<NotificationTarget>> {
+    let default_events = notify_events_from_env().context("Invalid NOTIFY_EVENTS")?;
+    let default_templates = Templates::from_env("NOTIFY", None)?;
+    let default_batch = batch_from_env("NOTIFY_BATCH_WINDOW", None)?;
+    let mut targets = Vec::new();
+
+    if let Ok(names) = env::var("NOTIFIERS") {
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let prefix = format!("NOTIFIER_{}", name.to_uppercase().replace('-', "_"));
+
+            let kind = env::var(format!("{prefix}_TYPE"))
+                .with_context(|| format!("{prefix}_TYPE is not set"))?
+                .to_lowercase();
+            let events = match env::var(format!("{prefix}_EVENTS")) {
+                Ok(events) => EventKind::parse_list(&events)
+                    .with_context(|| format!("Invalid {prefix}_EVENTS"))?,
+                Err(_) => default_events.clone(),
+            };
+            let batch = batch_from_env(&format!("{prefix}_BATCH_WINDOW"), default_batch)?;
+
+            targets.push(NotificationTarget {
+                name: name.to_string(),
+                notifier: notifier_from_env(&kind, &prefix)?.into(),
+                events,
+                templates: Templates::from_env(&prefix, Some(&default_templates))?,
+                batch: batch.map(|window| Arc::new(Batch::new(window))),
+            });
+        }
+    }
+
+    if let Ok(webhook_url) = env::var("DISCORD_WEBHOOK_URL") {
+        if targets.iter().any(|target| target.name == "discord") {
+            bail!("DISCORD_WEBHOOK_URL can not be used together with a notifier named discord");
+        }
+
+        targets.push(NotificationTarget {
+            name: "discord".to_string(),
+            notifier: Arc::new(Discord { webhook_url }),
+            events: default_events,
+            templates: default_templates,
+            batch: default_batch.map(|window| Arc::new(Batch::new(window))),
+        });
+    }
+
+    for target in &targets {
+        let mut events = target
+            .events
+            .iter()
+            .map(EventKind::as_str)
+            .collect::<Vec<_>>();
+        events.sort();
+        info!(
+            "Notifier {} ({}) gets {events:?}",
+            target.name,
+            target.kind()
+        );
+    }
+
+    Ok(targets)
+}
+
+/// Targets interested in `kind` and the queue to deliver through.
+fn targets_for(
+    state: &Arc<RwLock<AppState>>,
+    kind: EventKind,
+) -> Option<(Vec<NotificationTarget>, NotificationQueue)> {
+    let read_guard = state.read().ok()?;
+
+    let targets = read_guard
+        .notification_targets
+        .iter()
+        .filter(|target| target.events.contains(&kind))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    Some((targets, read_guard.notification_queue.clone()))
+}
+
+/// Queue the pending batch of `target`, if any.
+fn flush_batch(target: NotificationTarget, queue: &NotificationQueue) {
+    if let Some(event) = target.batch.as_ref().and_then(|batch| batch.take()) {
+        queue.push(target, event, None);
+    }
+}
+
+/// Queue `event` for all notification targets which are interested in it. The outcome is
+/// recorded on `record` once delivered, failures must not change the outcome of the request.
+pub(crate) fn notify(state: &Arc<RwLock<AppState>>, record: &Record, mut event: Event) {
+    let Some((targets, queue)) = targets_for(state, event.kind) else {
+        return;
+    };
+    if targets.is_empty() {
+        return;
+    }
+
+    event.targets = record.calls().unwrap_or_default();
+    info!("Notifying about {}.", event.kind.as_str());
+
+    for target in targets {
+        let action = format!("notify ({})", target.kind());
+
+        if record.skips(&target.name, &action) {
+            record.call(&target.name, &action, true, event.kind.as_str());
+            continue;
+        }
+
+        if let Some(batch) = &target.batch {
+            if batch.push(event.clone()) {
+                let window = batch.window;
+                let target = target.clone();
+                let queue = queue.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(window).await;
+                    flush_batch(target, &queue);
+                });
+            }
+            record.call(&target.name, &action, true, "batched");
+            continue;
+        }
+
+        queue.push(target, event.clone(), Some(record.clone()));
+    }
+}
+
+/// Queue an event which does not belong to a request, e.g. the daily digest. Batching is
+/// skipped.
+pub(crate) fn notify_unrecorded(state: &Arc<RwLock<AppState>>, event: Event) {
+    let Some((targets, queue)) = targets_for(state, event.kind) else {
+        return;
+    };
+
+    for target in targets {
+        queue.push(target, event.clone(), None);
+    }
+}
+
+/// Send all pending batches and wait for the queue to be empty, e.g. before a command exits.
+pub(crate) async fn flush_notifications(state: &Arc<RwLock<AppState>>) {
+    let (targets, queue) = match state.read() {
+        Ok(read_guard) => (
+            read_guard.notification_targets.clone(),
+            read_guard.notification_queue.clone(),
+        ),
+        Err(_) => return,
+    };
+
+    for target in targets {
+        flush_batch(target, &queue);
+    }
+    queue.flush().await;
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use anyhow::Context;
+
+use tokio::sync::{mpsc, oneshot};
+
+use super::backends::RetryAfter;
+use super::{Event, NotificationTarget};
+use crate::db::Record;
+
+/// Notifications waiting for delivery to a target, further ones are dropped.
+const QUEUE_SIZE: usize = 1000;
+/// First wait between two attempts, doubled with every retry.
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+/// Longest wait between two attempts, however many retries `NOTIFY_RETRIES` allows
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+enum Message {
+    Deliver {
+        target: NotificationTarget,
+        event: Box<Event>,
+        /// History entry the outcome is recorded on
+        record: Option<Record>,
+    },
+    /// Answered once all notifications queued before are handled
+    Flush(oneshot::Sender<()>),
+}
+
+/// Delivers notifications in the background, one after another per target, so a slow or
+/// unavailable service does not hold up requests. Every target has a queue of its own, waiting
+/// for a rate limited service doesn't delay the notifications to the others.
+#[derive(Clone)]
+pub(crate) struct NotificationQueue {
+    retries: u32,
+    /// Queues by target name, each one delivered by its own task
+    senders: Arc<Mutex<HashMap<String, mpsc::Sender<Message>>>>,
+}
+
+impl NotificationQueue {
+    /// `NOTIFY_RETRIES` (default 3) sets how often failed deliveries are retried.
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let retries = match env::var("NOTIFY_RETRIES") {
+            Ok(retries) => retries.parse().context("Invalid NOTIFY_RETRIES")?,
+            Err(_) => 3,
+        };
+
+        Ok(NotificationQueue {
+            retries,
+            senders: Arc::default(),
+        })
+    }
+
+    /// The queue of the target called `name`, its delivery task is started with the first
+    /// notification.
+    fn sender(&self, name: &str) -> Option<mpsc::Sender<Message>> {
+        let mut senders = self.senders.lock().ok()?;
+
+        let sender = senders.entry(name.to_string()).or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+            tokio::spawn(deliver_all(receiver, self.retries));
+            sender
+        });
+        Some(sender.clone())
+    }
+
+    pub(crate) fn push(&self, target: NotificationTarget, event: Event, record: Option<Record>) {
+        let name = target.name.clone();
+        let message = Message::Deliver {
+            target,
+            event: Box::new(event),
+            record,
+        };
+
+        let queued = self
+            .sender(&name)
+            .is_some_and(|sender| sender.try_send(message).is_ok());
+        if !queued {
+            warn!("Notification queue is full, dropped notification to {name}.");
+        }
+    }
+
+    /// Wait until all queued notifications are delivered or given up on.
+    pub(crate) async fn flush(&self) {
+        let senders = match self.senders.lock() {
+            Ok(senders) => senders.values().cloned().collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+
+        // the queues keep delivering meanwhile, waiting for them one by one takes as long as
+        // waiting for the slowest
+        for sender in senders {
+            let (done, receiver) = oneshot::channel();
+
+            if sender.send(Message::Flush(done)).await.is_ok() {
+                let _ = receiver.await;
+            }
+        }
+    }
+}
+
+async fn deliver_all(mut receiver: mpsc::Receiver<Message>, retries: u32) {
+    while let Some(message) = receiver.recv().await {
+        match message {
+            Message::Deliver {
+                target,
+                event,
+                record,
+            } => {
+                let result = deliver(&target, &event, retries).await;
+
+                if let Err(err) = &result {
+                    warn!("Could not send notification to {}: {err}", target.name);
+                }
+                if let Some(record) = record {
+                    let action = format!("notify ({})", target.kind());
+                    match result {
+                        Ok(()) => record.call(&target.name, &action, true, event.kind.as_str()),
+                        Err(err) => record.call(&target.name, &action, false, &err),
+                    }
+                }
+            }
+            Message::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Whether retrying can help, requests the service rejected won't work the next time either.
+fn retryable(err: &anyhow::Error) -> bool {
+    let status = err
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status);
+
+    !status.is_some_and(|status| status.is_client_error())
+}
+
+/// Wait before the retry after `attempt` failed attempts, doubling each time up to
+/// `MAX_RETRY_BACKOFF`.
+fn backoff(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .and_then(|factor| RETRY_BACKOFF.checked_mul(factor))
+        .map_or(MAX_RETRY_BACKOFF, |wait| wait.min(MAX_RETRY_BACKOFF))
+}
+
+async fn deliver(target: &NotificationTarget, event: &Event, retries: u32) -> anyhow::Result<()> {
+    let mut attempt = 0;
+
+    loop {
+        let err = match target.deliver(event).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        if attempt >= retries || !retryable(&err) {
+            return Err(err);
+        }
+
+        let wait = match err.downcast_ref::<RetryAfter>() {
+            Some(RetryAfter(retry_after)) => *retry_after,
+            None => backoff(attempt),
+        };
+        attempt += 1;
+
+        info!(
+            "Sending notification to {} failed ({err}), retry {attempt} of {retries} in {}.",
+            target.name,
+            humantime::format_duration(wait)
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_maximum() {
+        assert_eq!(backoff(0), Duration::from_secs(5));
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(9), Duration::from_secs(5 * 512));
+        assert_eq!(backoff(10), MAX_RETRY_BACKOFF);
+        // no overflow with any number of retries
+        assert_eq!(backoff(31), MAX_RETRY_BACKOFF);
+        assert_eq!(backoff(32), MAX_RETRY_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_RETRY_BACKOFF);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::env;
+
+use anyhow::Context;
+
+use minijinja::{context, Environment};
+
+use super::{Event, EventKind, ALL_EVENTS};
+
+/// User defined message templates, resolved per event.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Templates {
+    events: HashMap<EventKind, String>,
+}
+
+impl Templates {
+    /// Read the templates from `<prefix>_TEMPLATE`, used for all events but the daily digest, and
+    /// `<prefix>_TEMPLATE_<EVENT>`, e.g. `NOTIFY_TEMPLATE_XSEED_FAILED`. Events without a template
+    /// use the one of `fallback`.
+    pub(crate) fn from_env(prefix: &str, fallback: Option<&Templates>) -> anyhow::Result<Self> {
+        let default = env::var(format!("{prefix}_TEMPLATE")).ok();
+        let mut templates = Templates::default();
+
+        for kind in ALL_EVENTS {
+            let var = format!(
+                "{prefix}_TEMPLATE_{}",
+                kind.as_str().to_uppercase().replace('-', "_")
+            );
+            let default = default.clone().filter(|_| *kind != EventKind::DailyDigest);
+            let template = env::var(&var)
+                .ok()
+                .or(default)
+                .or_else(|| fallback.and_then(|f| f.events.get(kind).cloned()));
+
+            if let Some(template) = template {
+                // report syntax errors on startup instead of with the first notification
+                Environment::new()
+                    .template_from_str(&template)
+                    .with_context(|| format!("Invalid template for {var}"))?;
+                templates.events.insert(*kind, template);
+            }
+        }
+
+        Ok(templates)
+    }
+
+    /// Render the template for `event`. Returns `None` if there is none.
+    pub(crate) fn render(&self, event: &Event) -> anyhow::Result<Option<String>> {
+        let Some(template) = self.events.get(&event.kind) else {
+            return Ok(None);
+        };
+
+        let release = &event.release;
+        let message = Environment::new().render_str(
+            template,
+            context! {
+                event => event.kind.as_str(),
+                headline => event.headline(),
+                success => event.kind.success(),
+                release_title => release.release_title,
+                indexer => release.indexer,
+                release_type => release.release_type,
+                client => release.client,
+                download_id => release.download_id,
+                poster => release.poster,
+                link => release.link,
+                targets => event.targets,
+                error => event.error,
+                message => event.message(),
+            },
+        )?;
+
+        Ok(Some(message))
+    }
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tracing::{error, info};
+
+use anyhow::{bail, Context};
+
+use axum::{middleware::AddExtension, Extension};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use rustls::{
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Files needed to serve over TLS.
+#[derive(Debug, Clone)]
+pub(crate) struct TlsFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    /// CA used to verify client certificates, enables mutual TLS
+    client_ca: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    /// Read the certificate paths from the environment. Returns `None` if TLS is not configured.
+    pub(crate) fn from_env() -> anyhow::Result<Option<Self>> {
+        let cert = env::var("TLS_CERT_PATH").ok();
+        let key = env::var("TLS_KEY_PATH").ok();
+        let client_ca = env::var("TLS_CLIENT_CA_PATH").ok().map(PathBuf::from);
+
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok(Some(TlsFiles {
+                cert: cert.into(),
+                key: key.into(),
+                client_ca,
+            })),
+            (None, None) if client_ca.is_some() => {
+                bail!("TLS_CLIENT_CA_PATH requires TLS_CERT_PATH and TLS_KEY_PATH")
+            }
+            (None, None) => Ok(None),
+            _ => bail!("TLS_CERT_PATH and TLS_KEY_PATH have to be set together"),
+        }
+    }
+
+    /// Check that the files can be loaded.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        server_config(self).map(|_| ())
+    }
+
+    fn paths(&self) -> Vec<&Path> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths()
+            .into_iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Could not read certificates from {}", path.display()))?;
+
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Could not read private key from {}", path.display()))?
+        .with_context(|| format!("No private key found in {}", path.display()))
+}
+
+fn server_config(files: &TlsFiles) -> anyhow::Result<Arc<ServerConfig>> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &files.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca)? {
+                roots.add(cert)?;
+            }
+
+            // clients without a certificate are still accepted and have to use an API key
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(read_certs(&files.cert)?, read_key(&files.key)?)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Load the certificates and keep reloading them whenever one of the files changes.
+pub(crate) fn rustls_config(files: TlsFiles) -> anyhow::Result<RustlsConfig> {
+    let config = RustlsConfig::from_config(server_config(&files)?);
+
+    let reloaded = config.clone();
+    tokio::spawn(async move {
+        let mut modified = files.modified();
+
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+
+            let current = files.modified();
+            if current == modified {
+                continue;
+            }
+            modified = current;
+
+            match server_config(&files) {
+                Ok(server_config) => {
+                    reloaded.reload_from_config(server_config);
+                    info!("Reloaded TLS certificate.");
+                }
+                Err(err) => error!("Could not reload TLS certificate: {err:#}"),
+            }
+        }
+    });
+
+    Ok(config)
+}
+
+/// Common name of the verified certificate a client presented, available to the auth middleware
+/// as an `Extension`.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientCertificate(pub String);
+
+fn client_certificate<I>(stream: &TlsStream<I>) -> Option<ClientCertificate> {
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+
+    let name = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .unwrap_or("unnamed")
+        .to_string();
+
+    Some(ClientCertificate(name))
+}
+
+/// Rustls acceptor which adds the client certificate, if any, to every request of the connection.
+#[derive(Clone)]
+pub(crate) struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub(crate) fn new(config: RustlsConfig) -> Self {
+        ClientCertAcceptor {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, Option<ClientCertificate>>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let service = Extension(client_certificate(&stream)).layer(service);
+
+            Ok((stream, service))
+        })
+    }
+}
@@ -1,120 +1,164 @@
 use std::sync::{Arc, RwLock};
 
-use log::{error, info, trace, warn};
+use tracing::{error, info, trace, Span};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
+
+use clap::Args;
+
+use serde::Serialize;
 
 use tokio::time::{sleep, Duration};
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
 
+use crate::arr_events::{grabbed, with_grab};
+use crate::arr_instances::{arr_instance, route_instance, ArrInstance};
 use crate::cross_seed::{cross_seed_webhook, WebhookRequest};
-use crate::discord::discord_webhook;
+use crate::db::{EntryFields, Origin, Record};
+use crate::history::{db, finished_response, record_request};
+use crate::jobs::run_recorded_job;
+use crate::notify::{notify, Event, EventKind, Release};
 use crate::AppState;
 
 use crate::data_types::radarr::RadarrConnectWebhook;
 use crate::data_types::sonarr::SonarrConnectWebhook;
+use crate::data_types::{ArrEvent, ArrImport};
 
-enum ArrConnectWebhook {
-    Sonarr(SonarrConnectWebhook),
-    Radarr(RadarrConnectWebhook),
-}
-
-/// Use the `ArrConnectWebhook` to extract the correct path to send to cross-seed's webhook
-/// endpoint.
+/// Send the path of the import to the local cross-seed instance.
 async fn cross_seed_webhook_data(
     cross_seed_url: &str,
     cross_seed_api_key: &str,
-    request: &ArrConnectWebhook,
+    request: &ArrImport,
+    record: &Record,
 ) -> anyhow::Result<StatusCode> {
-    let path = match request {
-        ArrConnectWebhook::Sonarr(request) => {
-            let release = request.release.clone().context("No release found")?;
-            let episode_files = request
-                .episode_files
-                .clone()
-                .context("No episode_files found")?;
-            let destination_path = request
-                .destination_path
-                .clone()
-                .context("No destination_path found")?;
-            warn!("Release type: {}", release.release_type);
-            if release.release_type.to_lowercase() == "seasonpack" {
-                warn!("Release is a season pack, path: {destination_path}");
-                destination_path
-            } else {
-                episode_files
-                    .first()
-                    .context("Episode files are empty")?
-                    .path
-                    .clone()
-            }
-        }
-        ArrConnectWebhook::Radarr(request) => {
-            let movie_file = request.movie_file.clone().context("No movie_file found")?;
-            movie_file.path
-        }
-    };
+    let path = request
+        .import_path
+        .clone()
+        .context("Request does not include the path of the import.")?;
+    trace!("Release type: {:?}, path: {path}", request.release_type);
 
     let webhook = WebhookRequest::Path(path);
 
-    cross_seed_webhook(cross_seed_url, cross_seed_api_key, webhook).await
+    send_webhook(cross_seed_url, cross_seed_api_key, webhook, record).await
 }
 
-async fn xseed(request: ArrConnectWebhook, state: Arc<RwLock<AppState>>) -> anyhow::Result<()> {
-    let (cross_seed_url, cross_seed_api_key, xseed_torrent_clients, xseed_usenet_clients) = {
-        let read_guard = state
-            .read()
-            .map_err(|_| anyhow!("Could not read from state."))?;
-
-        let cross_seed_url = read_guard
-            .cross_seed_local_url
-            .clone()
-            .context("CROSS_SEED_LOCAL_URL is not set")?;
-        let cross_seed_api_key = read_guard
-            .cross_seed_local_api_key
-            .clone()
-            .context("CROSS_SEED_LOCAL_API_KEY is not set")?;
-
-        let xseed_torrent_clients = read_guard.xseed_torrent_clients.clone();
-        let xseed_usenet_clients = read_guard.xseed_usenet_clients.clone();
-
-        (
-            cross_seed_url,
-            cross_seed_api_key,
-            xseed_torrent_clients,
-            xseed_usenet_clients,
-        )
+/// Send `webhook` to the local cross-seed instance and record the call. In a dry run nothing is
+/// sent and cross-seed is assumed to have succeeded.
+pub(crate) async fn send_webhook(
+    cross_seed_url: &str,
+    cross_seed_api_key: &str,
+    webhook: WebhookRequest,
+    record: &Record,
+) -> anyhow::Result<StatusCode> {
+    let action = match &webhook {
+        WebhookRequest::InfoHash(_) => "webhook (infoHash)",
+        WebhookRequest::Path(_) => "webhook (path)",
     };
 
-    let event_type = match &request {
-        ArrConnectWebhook::Sonarr(request) => &request.event_type,
-        ArrConnectWebhook::Radarr(request) => &request.event_type,
+    let resp = if record.skips("cross-seed-local", action) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        cross_seed_webhook(cross_seed_url, cross_seed_api_key, webhook).await
     };
+    record.call_status("cross-seed-local", action, &resp);
+    resp
+}
 
-    if event_type == "Test" {
-        info!("[/xseed-*] Test event detected.");
-        return Ok(());
-    }
+/// URL and API key of the local cross-seed instance of `settings`.
+fn cross_seed_local(settings: &ArrInstance) -> anyhow::Result<(String, String)> {
+    let cross_seed_url = settings
+        .cross_seed_local_url
+        .clone()
+        .context("CROSS_SEED_LOCAL_URL is not set")?;
+    let cross_seed_api_key = settings
+        .cross_seed_local_api_key
+        .clone()
+        .context("CROSS_SEED_LOCAL_API_KEY is not set")?;
+
+    Ok((cross_seed_url, cross_seed_api_key))
+}
 
-    trace!("[/seed-*] EventType: {event_type}");
+/// Handle a Sonarr / Radarr event: imports are searched for cross-seeds, grabs are registered if
+/// enabled, see `ArrEventOptions`.
+pub(crate) async fn xseed(
+    request: ArrImport,
+    state: Arc<RwLock<AppState>>,
+    record: &Record,
+) -> anyhow::Result<()> {
+    trace!("EventType: {}", request.event_type);
+
+    match request.event {
+        ArrEvent::Test => {
+            info!("Test event detected.");
+            Ok(())
+        }
+        ArrEvent::Grab => grabbed(&state, Release::from(&request)),
+        // the replaced and deleted files are cleaned up by the inject endpoint
+        ArrEvent::Import | ArrEvent::Upgrade => xseed_import(&request, &state, record).await,
+        ArrEvent::FileDelete | ArrEvent::Other => {
+            info!("{} event ignored.", request.event_type);
+            Ok(())
+        }
+    }
+}
 
-    let download_id = match &request {
-        ArrConnectWebhook::Sonarr(request) => request.download_id.clone(),
-        ArrConnectWebhook::Radarr(request) => request.download_id.clone(),
+/// Search cross-seeds for the import and notify about the outcome.
+async fn xseed_import(
+    request: &ArrImport,
+    state: &Arc<RwLock<AppState>>,
+    record: &Record,
+) -> anyhow::Result<()> {
+    let release = with_grab(state, Release::from(request));
+
+    match search_cross_seeds(request, state, record).await {
+        Ok(false) => Ok(()),
+        Ok(true) => {
+            notify(state, record, Event::new(EventKind::XseedOk, release));
+            Ok(())
+        }
+        Err(err) => {
+            let event = Event::new(EventKind::XseedFailed, release).with_error(&err);
+            notify(state, record, event);
+            Err(err)
+        }
     }
-    .context("Request does not include a download_id.")?;
-    trace!("[/xseed-*] download_id: {download_id}");
+}
 
-    let client_id = match &request {
-        ArrConnectWebhook::Sonarr(request) => request.download_client.clone(),
-        ArrConnectWebhook::Radarr(request) => request.download_client.clone(),
+/// Returns whether cross-seed was asked to search, imports which are ignored return `false`.
+async fn search_cross_seeds(
+    request: &ArrImport,
+    state: &Arc<RwLock<AppState>>,
+    record: &Record,
+) -> anyhow::Result<bool> {
+    let settings = arr_instance(state, request.instance_name.as_deref())?;
+    let (cross_seed_url, cross_seed_api_key) = cross_seed_local(&settings)?;
+
+    if let Some(release_title) = &request.release_title {
+        Span::current().record("release_title", release_title);
     }
-    .context("Request does not include a download_client.")?;
-    trace!("[/xseed-*] client_id: {client_id}");
+
+    let download_id = request
+        .download_id
+        .clone()
+        .context("Request does not include a download_id.")?;
+    Span::current().record("download_id", &download_id);
+    trace!("download_id: {download_id}");
+
+    let client_id = request
+        .download_client
+        .clone()
+        .context("Request does not include a download_client.")?;
+    Span::current().record("client", &client_id);
+    trace!("client_id: {client_id}");
 
     let unique_id = format!("{download_id}-{client_id}");
-    trace!("[/xseed-*] Unique id: {unique_id}");
+    trace!("Unique id: {unique_id}");
 
     if state
         .read()
@@ -122,85 +166,133 @@ async fn xseed(request: ArrConnectWebhook, state: Arc<RwLock<AppState>>) -> anyh
         .xseed_unique_ids
         .contains(&unique_id)
     {
-        info!("[/xseed-*] Download ID [{unique_id}] already processed");
-        return Ok(());
+        info!("Download ID [{unique_id}] already processed");
+        return Ok(false);
     }
 
-    let torrent_client = xseed_torrent_clients
+    let torrent_client = settings
+        .xseed_torrent_clients
         .filter(|clients| clients.contains(&client_id))
         .map(|_| client_id.clone());
 
-    let usenet_client = xseed_usenet_clients
+    let usenet_client = settings
+        .xseed_usenet_clients
         .filter(|clients| clients.contains(&client_id))
         .map(|_| client_id.clone());
 
     let resp = if let Some(torrent_client) = torrent_client {
-        info!("[/xseed-*] Processing torrent client operations for {torrent_client}");
+        info!("Processing torrent client operations for {torrent_client}");
 
         // send cross-seed webhook request with infoHash
         let info_hash = download_id.to_string();
         let webhook = WebhookRequest::InfoHash(info_hash);
-        let resp = cross_seed_webhook(&cross_seed_url, &cross_seed_api_key, webhook).await?;
+        let resp = send_webhook(&cross_seed_url, &cross_seed_api_key, webhook, record).await?;
 
         if resp == StatusCode::from_u16(204).unwrap() {
             resp
         } else {
             sleep(Duration::from_secs(15)).await;
             // send cross-seed webhook request with path
-            cross_seed_webhook_data(&cross_seed_url, &cross_seed_api_key, &request).await?
+            cross_seed_webhook_data(&cross_seed_url, &cross_seed_api_key, request, record).await?
         }
     } else if let Some(usenet_client) = usenet_client {
-        info!("[/xseed-*] Processing usenet client operations for {usenet_client}");
+        info!("Processing usenet client operations for {usenet_client}");
 
         // send cross-seed webhook request with path
-        cross_seed_webhook_data(&cross_seed_url, &cross_seed_api_key, &request).await?
+        cross_seed_webhook_data(&cross_seed_url, &cross_seed_api_key, request, record).await?
     } else {
-        info!("[/xseed-*] Unrecognized client {client_id}.");
-        return Ok(());
+        info!("Unrecognized client {client_id}.");
+        return Ok(false);
     };
 
-    trace!("[/xseed-*] cross-seed API response: {resp}");
+    trace!("cross-seed API response: {resp}");
 
     if resp == StatusCode::from_u16(204).unwrap() {
-        // update xseed_unique_ids
-        state
-            .write()
-            .map_err(|_| anyhow!("Could not write to state."))?
-            .xseed_unique_ids
-            .insert(unique_id);
-        info!("[/xseed-*] cross-seed completed successfully.");
-
-        let release_title = match &request {
-            ArrConnectWebhook::Sonarr(request) => request.release.clone().unwrap().release_title,
-            ArrConnectWebhook::Radarr(request) => request.release.clone().unwrap().release_title,
-        };
-
-        let discord_webhook_url = {
-            let read_guard = state
-                .read()
-                .map_err(|_| anyhow!("Could not read from state."))?;
-
-            read_guard.discord_webhook_url.clone()
-        };
-
-        if let Some(discord_webhook_url) = discord_webhook_url {
-            let content = format!("[/xseed-*] cross-seed completed successfully ({release_title})");
-            discord_webhook(&discord_webhook_url, &content).await?;
+        // update xseed_unique_ids, a dry run must not keep the real request from being handled
+        if !record.dry_run() {
+            state
+                .write()
+                .map_err(|_| anyhow!("Could not write to state."))?
+                .xseed_unique_ids
+                .insert(unique_id);
         }
-
-        Ok(())
+        info!("cross-seed completed successfully.");
+        Ok(true)
     } else {
-        info!("[/xseed-*] cross-seed failed with status code: {resp}");
+        info!("cross-seed failed with status code: {resp}");
         Err(anyhow!("cross-seed failed with status code: {resp}"))
     }
 }
 
+/// What to search cross-seeds for from the command line.
+#[derive(Args, Serialize)]
+#[group(required = true, multiple = false)]
+pub(crate) struct XseedTarget {
+    /// Info hash of a torrent in the client cross-seed is connected to
+    #[arg(long)]
+    info_hash: Option<String>,
+    /// Path of the downloaded data
+    #[arg(long)]
+    path: Option<String>,
+}
+
+/// Send a single torrent or path to the local cross-seed instance.
+pub(crate) async fn xseed_command(
+    state: Arc<RwLock<AppState>>,
+    target: XseedTarget,
+) -> anyhow::Result<()> {
+    let fields = EntryFields {
+        download_id: target.info_hash.clone(),
+        ..Default::default()
+    };
+    let origin = Origin::command_line(&state);
+    let record = db(&state)?.start("cli:xseed", &origin, fields, &target)?;
+
+    let webhook = match (target.info_hash, target.path) {
+        (Some(info_hash), _) => WebhookRequest::InfoHash(info_hash),
+        (None, Some(path)) => WebhookRequest::Path(path),
+        (None, None) => bail!("Either --info-hash or --path is required."),
+    };
+
+    let job = {
+        let state = state.clone();
+        |record: Record| async move {
+            let (cross_seed_url, cross_seed_api_key) =
+                cross_seed_local(&arr_instance(&state, None)?)?;
+
+            let resp = send_webhook(&cross_seed_url, &cross_seed_api_key, webhook, &record).await?;
+
+            if resp != StatusCode::NO_CONTENT {
+                bail!("cross-seed failed with status code: {resp}");
+            }
+
+            info!("cross-seed completed successfully.");
+            Ok(())
+        }
+    };
+    run_recorded_job(&state, record, job).await
+}
+
 pub(crate) async fn xseed_radarr(
     State(state): State<Arc<RwLock<AppState>>>,
-    Json(payload): Json<RadarrConnectWebhook>,
+    origin: Origin,
+    instance: Option<Path<String>>,
+    Json(mut payload): Json<RadarrConnectWebhook>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    match xseed(ArrConnectWebhook::Radarr(payload), state).await {
-        Ok(_) => Ok(StatusCode::OK),
+    // the instance in the route wins and is stored with the request for replays
+    if let Some(instance) = route_instance(&state, instance.map(|Path(i)| i))? {
+        payload.instance_name = Some(instance);
+    }
+    let request = ArrImport::from(&payload);
+    let fields = EntryFields::from(&request);
+    let record = record_request(&state, "/xseed-radarr", &origin, fields, &payload);
+
+    let job = {
+        let state = state.clone();
+        |record: Record| async move { xseed(request, state, &record).await }
+    };
+    match run_recorded_job(&state, record.clone(), job).await {
+        Ok(_) => Ok(finished_response(&record)),
         Err(err) => {
             error!("Error occured: {err}");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -210,10 +302,24 @@ pub(crate) async fn xseed_radarr(
 
 pub(crate) async fn xseed_sonarr(
     State(state): State<Arc<RwLock<AppState>>>,
-    Json(payload): Json<SonarrConnectWebhook>,
+    origin: Origin,
+    instance: Option<Path<String>>,
+    Json(mut payload): Json<SonarrConnectWebhook>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    match xseed(ArrConnectWebhook::Sonarr(payload), state).await {
-        Ok(_) => Ok(StatusCode::OK),
+    // the instance in the route wins and is stored with the request for replays
+    if let Some(instance) = route_instance(&state, instance.map(|Path(i)| i))? {
+        payload.instance_name = Some(instance);
+    }
+    let request = ArrImport::from(&payload);
+    let fields = EntryFields::from(&request);
+    let record = record_request(&state, "/xseed-sonarr", &origin, fields, &payload);
+
+    let job = {
+        let state = state.clone();
+        |record: Record| async move { xseed(request, state, &record).await }
+    };
+    match run_recorded_job(&state, record.clone(), job).await {
+        Ok(_) => Ok(finished_response(&record)),
         Err(err) => {
             error!("Error occured: {err}");
             Err(StatusCode::INTERNAL_SERVER_ERROR)